@@ -0,0 +1,2 @@
+-- Local times of day a task fires at, empty means every interval from nextRun
+ALTER TABLE schedule ADD COLUMN times TIME[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,9 @@
+-- Optional end conditions, a task stops once either is reached
+ALTER TABLE schedule ADD COLUMN endDate TIMESTAMP;
+ALTER TABLE schedule ADD COLUMN maxRuns INTEGER;
+ALTER TABLE schedule ADD COLUMN runs INTEGER NOT NULL DEFAULT 0;
+
+-- Finished tasks are kept around instead of being deleted
+ALTER TABLE schedule ADD COLUMN archived TIMESTAMP;
+
+CREATE INDEX on schedule(archived);
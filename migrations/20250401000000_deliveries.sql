@@ -0,0 +1,17 @@
+-- Reminders waiting to be sent, queued in the same transaction that moves the schedule on
+CREATE TABLE deliveries(
+  id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  scheduleID BIGINT NOT NULL REFERENCES schedule(id) ON DELETE CASCADE,
+  -- Which run of the schedule this is, a run is only ever queued once
+  run INTEGER NOT NULL,
+  runAt TIMESTAMP NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  nextAttempt TIMESTAMP NOT NULL,
+  leasedBy TEXT,
+  leasedUntil TIMESTAMP,
+  messageID BIGINT,
+  sent TIMESTAMP,
+  UNIQUE(scheduleID, run)
+);
+
+CREATE INDEX on deliveries(sent, nextAttempt);
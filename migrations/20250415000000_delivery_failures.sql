@@ -0,0 +1,3 @@
+-- Why the last attempt at a delivery failed, and when puppy gave up on it
+ALTER TABLE deliveries ADD COLUMN lastError TEXT;
+ALTER TABLE deliveries ADD COLUMN failed TIMESTAMP;
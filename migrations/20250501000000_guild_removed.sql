@@ -0,0 +1,4 @@
+-- When puppy was removed from the guild, it is purged once the schedules paused with it are
+ALTER TABLE guilds ADD COLUMN removed TIMESTAMP;
+
+CREATE INDEX on guilds(removed);
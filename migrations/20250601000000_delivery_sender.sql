@@ -0,0 +1,4 @@
+-- Which instance sent a delivery, so reactions to it are only answered by that instance
+ALTER TABLE deliveries ADD COLUMN sentBy TEXT;
+
+CREATE INDEX on deliveries(messageID);
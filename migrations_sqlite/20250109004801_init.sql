@@ -0,0 +1,32 @@
+-- SQLite version of migrations/20250109004801_init.sql
+-- Intervals and timezones are stored as microseconds and timestamps as RFC 3339 text in UTC
+-- Column names are lower case as SQLite reports them the way they are declared
+CREATE TABLE guilds(
+  guildid INTEGER NOT NULL UNIQUE PRIMARY KEY,
+  channel INTEGER NOT NULL
+);
+
+CREATE TABLE users(
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  guildid INTEGER NOT NULL REFERENCES guilds(guildid),
+  userid INTEGER NOT NULL,
+  praise TEXT NOT NULL,
+  praisename TEXT NOT NULL,
+  timezone INTEGER NOT NULL
+);
+
+CREATE INDEX users_guild_user ON users(guildid, userid);
+
+CREATE TABLE schedule(
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  guildid INTEGER NOT NULL REFERENCES guilds(guildid),
+  userid INTEGER NOT NULL REFERENCES users(id),
+  task TEXT NOT NULL,
+  tasksecondary TEXT NOT NULL,
+  interval INTEGER NOT NULL,
+  created TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')),
+  nextrun TEXT NOT NULL
+);
+
+CREATE INDEX schedule_nextrun ON schedule(nextrun);
+CREATE INDEX schedule_guild_user ON schedule(guildid, userid);
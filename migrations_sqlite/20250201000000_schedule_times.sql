@@ -0,0 +1,2 @@
+-- Local times of day a task fires at as comma separated HH:MM:SS, empty means every interval from nextrun
+ALTER TABLE schedule ADD COLUMN times TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,8 @@
+-- Why a schedule was archived, 'finished' or 'deleted'
+ALTER TABLE schedule ADD COLUMN archivereason TEXT;
+UPDATE schedule SET archivereason = 'finished' WHERE archived IS NOT NULL;
+
+-- Users are archived with their schedules so they can be restored
+ALTER TABLE users ADD COLUMN archived TEXT;
+
+CREATE INDEX users_archived ON users(archived);
@@ -0,0 +1,17 @@
+-- Reminders waiting to be sent, queued in the same transaction that moves the schedule on
+CREATE TABLE deliveries(
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  scheduleid INTEGER NOT NULL REFERENCES schedule(id) ON DELETE CASCADE,
+  -- Which run of the schedule this is, a run is only ever queued once
+  run INTEGER NOT NULL,
+  runat TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  nextattempt TEXT NOT NULL,
+  leasedby TEXT,
+  leaseduntil TEXT,
+  messageid INTEGER,
+  sent TEXT,
+  UNIQUE(scheduleid, run)
+);
+
+CREATE INDEX deliveries_pending ON deliveries(sent, nextattempt);
@@ -0,0 +1,4 @@
+-- Which instance sent a delivery, so reactions to it are only answered by that instance
+ALTER TABLE deliveries ADD COLUMN sentby TEXT;
+
+CREATE INDEX deliveries_message ON deliveries(messageid);
@@ -1,11 +1,44 @@
-use crate::repo::database::*;
+use crate::repo::storage::*;
 use crate::{Context, Error};
 
 use poise::serenity_prelude as serenity;
 
-use chrono::TimeDelta;
+use chrono::{Offset, TimeDelta};
 use sqlx::postgres::types::PgInterval;
-use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::chrono::NaiveTime;
+
+/// The guild a command was used in. Commands are all guild only so this is
+/// just a guard for poise letting one through.
+fn guild_id(ctx: Context<'_>) -> Result<serenity::GuildId, Error> {
+    match ctx.guild_id() {
+        Some(e) => Ok(e),
+        None => Err(Error::Validation(
+            "Puppy only knows how to do that in a server!!".to_owned(),
+        )),
+    }
+}
+
+fn timezone_interval(hour: i8, minutes: i8) -> Result<PgInterval, Error> {
+    match crate::util::timezone_interval(hour, minutes) {
+        Some(e) => Ok(e),
+        None => {
+            let res = "Puppy only knows timezones from -12:00 to 14:59.\nPlease set the hour from -12 to 14 and the minutes from 0 to 59.";
+            Err(Error::Validation(res.to_owned()))
+        }
+    }
+}
+
+fn end_of_local_day(
+    date: chrono::NaiveDate,
+    timezone: &PgInterval,
+) -> Result<chrono::DateTime<chrono::Utc>, Error> {
+    match crate::util::end_of_local_day(date, timezone) {
+        Some(e) => Ok(e),
+        None => Err(Error::Validation(
+            "Puppy can't count that far ahead!!\nPlease pick an earlier end date.".to_owned(),
+        )),
+    }
+}
 
 fn generate_task_table(tasks: &Vec<UserTask>) -> String {
     let mut res = serenity::MessageBuilder::new();
@@ -19,14 +52,26 @@ fn generate_task_table(tasks: &Vec<UserTask>) -> String {
             " | {} | {} | {} | {} | <t:{}:R> | <t:{}:R>\n",
             task.task,
             task.task_secondary,
-            crate::util::pginterval_to_string(&task.interval),
+            match crate::util::end_to_string(
+                task.runs,
+                task.max_runs,
+                task.end_date,
+                &task.timezone
+            ) {
+                Some(end) => format!(
+                    "{} ({})",
+                    crate::util::schedule_to_string(&task.interval, &task.times),
+                    end
+                ),
+                None => crate::util::schedule_to_string(&task.interval, &task.times),
+            },
             chrono::DateTime::<chrono::FixedOffset>::from_naive_utc_and_offset(
                 task.next_run.naive_utc(),
                 chrono::FixedOffset::east_opt(
                     chrono::TimeDelta::microseconds(task.timezone.microseconds).num_seconds()
                         as i32
                 )
-                .expect("Cannot conver time")
+                .unwrap_or(chrono::Utc.fix())
             )
             .to_rfc2822(),
             task.next_run.timestamp(),
@@ -37,31 +82,230 @@ fn generate_task_table(tasks: &Vec<UserTask>) -> String {
     res.build()
 }
 
+fn task_choice(task: &UserTask) -> serenity::AutocompleteChoice {
+    let next_run = chrono::DateTime::<chrono::FixedOffset>::from_naive_utc_and_offset(
+        task.next_run.naive_utc(),
+        chrono::FixedOffset::east_opt(
+            chrono::TimeDelta::microseconds(task.timezone.microseconds).num_seconds() as i32,
+        )
+        .unwrap_or(chrono::Utc.fix()),
+    );
+
+    let mut name = format!(
+        "{} | {} | next {}",
+        task.id,
+        task.task,
+        next_run.format("%a %e %b %H:%M")
+    );
+
+    // Discord rejects choice names longer than 100 characters
+    if name.chars().count() > 100 {
+        name = name.chars().take(97).collect::<String>() + "...";
+    }
+
+    serenity::AutocompleteChoice::new(name, task.id)
+}
+
+fn filter_task_choices(tasks: Vec<UserTask>, partial: &str) -> Vec<serenity::AutocompleteChoice> {
+    let partial = partial.trim().to_lowercase();
+
+    tasks
+        .iter()
+        .filter(|t| {
+            partial.is_empty()
+                || t.id.to_string().starts_with(&partial)
+                || t.task.to_lowercase().contains(&partial)
+        })
+        .take(25)
+        .map(task_choice)
+        .collect()
+}
+
+/// Lists the invoking user's tasks for commands that take a task ID
+async fn autocomplete_task_user(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<serenity::AutocompleteChoice> {
+    let guild = match ctx.guild_id() {
+        Some(e) => e.get() as i64,
+        None => return Vec::new(),
+    };
+    let user = ctx.author().id.get() as i64;
+
+    match ctx.data().db.get_task_user(&guild, &user).await {
+        Ok(tasks) => filter_task_choices(tasks, partial),
+        Err(e) => {
+            tracing::warn!(error = %e, "cannot fetch tasks for autocomplete");
+            Vec::new()
+        }
+    }
+}
+
+/// Lists the target user's tasks for admin commands that take a task ID.
+/// Falls back to every task in the guild when no user has been picked yet.
+async fn autocomplete_task_admin(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<serenity::AutocompleteChoice> {
+    let guild = match ctx.guild_id() {
+        Some(e) => e.get() as i64,
+        None => return Vec::new(),
+    };
+
+    let target =
+        match ctx {
+            poise::Context::Application(app) => app.interaction.data.options.iter().find_map(|o| {
+                match (o.name.as_str(), &o.value) {
+                    ("user", serenity::CommandDataOptionValue::User(u)) => Some(u.get() as i64),
+                    _ => None,
+                }
+            }),
+            poise::Context::Prefix(_) => None,
+        };
+
+    let tasks = match target {
+        Some(user) => ctx.data().db.get_task_user(&guild, &user).await,
+        None => ctx.data().db.get_task_guild(&guild).await,
+    };
+
+    match tasks {
+        Ok(tasks) => filter_task_choices(tasks, partial),
+        Err(e) => {
+            tracing::warn!(error = %e, "cannot fetch tasks for autocomplete");
+            Vec::new()
+        }
+    }
+}
+
+fn filter_archived_choices(
+    tasks: Vec<ArchivedTask>,
+    partial: &str,
+) -> Vec<serenity::AutocompleteChoice> {
+    let partial = partial.trim().to_lowercase();
+
+    tasks
+        .iter()
+        .filter(|t| {
+            partial.is_empty()
+                || t.id.to_string().starts_with(&partial)
+                || t.task.to_lowercase().contains(&partial)
+        })
+        .take(25)
+        .map(|t| {
+            let mut name = format!(
+                "{} | {} | deleted {}",
+                t.id,
+                t.task,
+                crate::util::format_local(&t.archived, &t.timezone)
+            );
+
+            if name.chars().count() > 100 {
+                name = name.chars().take(97).collect::<String>() + "...";
+            }
+
+            serenity::AutocompleteChoice::new(name, t.id)
+        })
+        .collect()
+}
+
+/// Lists the invoking user's deleted tasks that can still be restored
+async fn autocomplete_deleted_user(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<serenity::AutocompleteChoice> {
+    let guild = match ctx.guild_id() {
+        Some(e) => e.get() as i64,
+        None => return Vec::new(),
+    };
+    let user = ctx.author().id.get() as i64;
+
+    match ctx.data().db.get_deleted_tasks(&guild, Some(&user)).await {
+        Ok(tasks) => filter_archived_choices(tasks, partial),
+        Err(e) => {
+            tracing::warn!(error = %e, "cannot fetch deleted tasks for autocomplete");
+            Vec::new()
+        }
+    }
+}
+
+/// Lists deleted tasks in the guild, or just the target user's if one has been picked
+async fn autocomplete_deleted_admin(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<serenity::AutocompleteChoice> {
+    let guild = match ctx.guild_id() {
+        Some(e) => e.get() as i64,
+        None => return Vec::new(),
+    };
+
+    let target =
+        match ctx {
+            poise::Context::Application(app) => app.interaction.data.options.iter().find_map(|o| {
+                match (o.name.as_str(), &o.value) {
+                    ("user", serenity::CommandDataOptionValue::User(u)) => Some(u.get() as i64),
+                    _ => None,
+                }
+            }),
+            poise::Context::Prefix(_) => None,
+        };
+
+    match ctx
+        .data()
+        .db
+        .get_deleted_tasks(&guild, target.as_ref())
+        .await
+    {
+        Ok(tasks) => filter_archived_choices(tasks, partial),
+        Err(e) => {
+            tracing::warn!(error = %e, "cannot fetch deleted tasks for autocomplete");
+            Vec::new()
+        }
+    }
+}
+
 #[poise::command(
     prefix_command,
     slash_command,
+    guild_only,
     default_member_permissions = "ADMINISTRATOR"
 )]
 pub async fn setchannel(ctx: Context<'_>) -> Result<(), Error> {
     let guild = Guild {
-        id: ctx.guild().unwrap().id.get().try_into().unwrap(),
-        channel: ctx.channel_id().get().try_into().unwrap(),
+        id: guild_id(ctx)?.get() as i64,
+        channel: ctx.channel_id().get() as i64,
     };
     match ctx.data().db.update_guild(&guild).await {
         Ok(_) => {
-            let response = format!("Bark Bark!!! You've successfully shown me where my home is!!\nPlease make sure I have permissions to message in this channel");
-            ctx.say(response).await?;
-            Ok(())
-        }
-        Err(_) => {
-            let response = format!("Server Error");
+            let mut response = String::from("Bark Bark!!! You've successfully shown me where my home is!!\nPlease make sure I have permissions to message in this channel");
+
+            // Tasks paused when the old channel was deleted can go out here instead
+            match ctx
+                .data()
+                .db
+                .resume_tasks(
+                    &guild.id,
+                    None,
+                    PAUSED_CHANNEL_DELETED,
+                    &ctx.data().clock.now(),
+                )
+                .await
+            {
+                Ok(0) => (),
+                Ok(e) => {
+                    ctx.data().wakeup.notify_one();
+                    response += &format!("\nPuppy has started {} paused reminders again!!", e);
+                }
+                Err(e) => tracing::error!(error = %e, guild = guild.id, "cannot resume tasks"),
+            };
+
             ctx.say(response).await?;
             Ok(())
         }
+        Err(e) => Err(e.into()),
     }
 }
 
-#[poise::command(prefix_command, slash_command)]
+#[poise::command(prefix_command, slash_command, guild_only)]
 pub async fn adduser(
     ctx: Context<'_>,
     #[description = "Praise"] praise: String,
@@ -75,16 +319,9 @@ pub async fn adduser(
     #[max = 59_u8]
     timezoneminutes: i8,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get();
+    let guild = guild_id(ctx)?.get();
 
-    let delta = match timezonehour >= 0_i8 {
-        true => TimeDelta::minutes(timezoneminutes as i64) + TimeDelta::hours(timezonehour as i64),
-        false => {
-            TimeDelta::minutes((timezoneminutes - (timezoneminutes * 2)) as i64)
-                + TimeDelta::hours(timezonehour as i64)
-        }
-    };
-    let interval = PgInterval::try_from(delta).expect("Delta can't convert to interval");
+    let interval = timezone_interval(timezonehour, timezoneminutes)?;
 
     let user_id = ctx.author().id;
 
@@ -107,31 +344,27 @@ pub async fn adduser(
             ctx.say(response).await?;
             return Ok(());
         }
-        Err(e) => {
-            println!("{:?}", e);
-            match e {
-                DatabaseErrors::GuildDoesNotExist => {
-                    let response = format!("Bark Bark!!!\nI'm new here, please have an admin run /setchannel before adding people.");
-                    ctx.say(response).await?;
-                    return Ok(());
-                }
-                DatabaseErrors::UserAlreadyExists => {
-                    let response = serenity::MessageBuilder::new()
-                        .mention(&user_id)
-                        .push(" is already my friend!!\nPlease use /updateuser to update them!!")
-                        .build();
-                    ctx.say(response).await?;
-                    return Ok(());
-                }
-                _ => return Err("Server Error".into()),
+        Err(e) => match e {
+            DatabaseErrors::GuildDoesNotExist => {
+                let response = format!("Bark Bark!!!\nI'm new here, please have an admin run /setchannel before adding people.");
+                return Err(Error::NotFound(response));
             }
-        }
+            DatabaseErrors::UserAlreadyExists => {
+                let response = serenity::MessageBuilder::new()
+                    .mention(&user_id)
+                    .push(" is already my friend!!\nPlease use /updateuser to update them!!")
+                    .build();
+                return Err(Error::Validation(response));
+            }
+            _ => return Err(e.into()),
+        },
     };
 }
 
 #[poise::command(
     prefix_command,
     slash_command,
+    guild_only,
     default_member_permissions = "ADMINISTRATOR"
 )]
 pub async fn adduseradmin(
@@ -148,16 +381,9 @@ pub async fn adduseradmin(
     #[max = 59_u8]
     timezoneminutes: i8,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get();
+    let guild = guild_id(ctx)?.get();
 
-    let delta = match timezonehour >= 0_i8 {
-        true => TimeDelta::minutes(timezoneminutes as i64) + TimeDelta::hours(timezonehour as i64),
-        false => {
-            TimeDelta::minutes((timezoneminutes - (timezoneminutes * 2)) as i64)
-                + TimeDelta::hours(timezonehour as i64)
-        }
-    };
-    let interval = PgInterval::try_from(delta).expect("Delta can't convert to interval");
+    let interval = timezone_interval(timezonehour, timezoneminutes)?;
 
     let user_id = user.id;
 
@@ -180,29 +406,24 @@ pub async fn adduseradmin(
             ctx.say(response).await?;
             return Ok(());
         }
-        Err(e) => {
-            println!("{:?}", e);
-            match e {
-                DatabaseErrors::GuildDoesNotExist => {
-                    let response = format!("Bark Bark!!!\nI'm new here, please have an admin run /setchannel before adding people.");
-                    ctx.say(response).await?;
-                    return Ok(());
-                }
-                DatabaseErrors::UserAlreadyExists => {
-                    let response = serenity::MessageBuilder::new()
-                        .mention(&user_id)
-                        .push(" is already my friend!!\nPlease use /updateuser to update them!!")
-                        .build();
-                    ctx.say(response).await?;
-                    return Ok(());
-                }
-                _ => return Err("Server Error".into()),
+        Err(e) => match e {
+            DatabaseErrors::GuildDoesNotExist => {
+                let response = format!("Bark Bark!!!\nI'm new here, please have an admin run /setchannel before adding people.");
+                return Err(Error::NotFound(response));
             }
-        }
+            DatabaseErrors::UserAlreadyExists => {
+                let response = serenity::MessageBuilder::new()
+                    .mention(&user_id)
+                    .push(" is already my friend!!\nPlease use /updateuser to update them!!")
+                    .build();
+                return Err(Error::Validation(response));
+            }
+            _ => return Err(e.into()),
+        },
     };
 }
 
-#[poise::command(prefix_command, slash_command)]
+#[poise::command(prefix_command, slash_command, guild_only)]
 pub async fn shiftschedule(
     ctx: Context<'_>,
     #[description = "hours to shift by -24 to 24 allowed"]
@@ -214,7 +435,7 @@ pub async fn shiftschedule(
     #[max = 59_u8]
     minutes: i8,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get();
+    let guild = guild_id(ctx)?.get();
     let user_id = ctx.author().id;
 
     match ctx
@@ -227,30 +448,32 @@ pub async fn shiftschedule(
         Err(e) => match e {
             DatabaseErrors::GuildDoesNotExist => {
                 let response = format!("Bark Bark!!!\nI'm new here, please have an admin run /setchannel before adding people.");
-                ctx.say(response).await?;
-                return Ok(());
+                return Err(Error::NotFound(response));
             }
             DatabaseErrors::UserDoesNotExist => {
                 let response = serenity::MessageBuilder::new()
                     .mention(&user_id)
                     .push(" is not my friend yet\nPlease use /adduser to make them my friend!!")
                     .build();
-                ctx.say(response).await?;
-                return Ok(());
+                return Err(Error::NotFound(response));
             }
-            _ => return Err("Server Error".into()),
+            _ => return Err(e.into()),
         },
     };
 
     let delta = match hour >= -1_i8 {
         true => TimeDelta::minutes(minutes as i64) + TimeDelta::hours(hour as i64),
-        false => {
-            TimeDelta::minutes((minutes - (minutes * 2)) as i64) + TimeDelta::hours(hour as i64)
-        }
+        false => TimeDelta::minutes(-(minutes as i64)) + TimeDelta::hours(hour as i64),
     };
 
-    let interval: PgInterval =
-        PgInterval::try_from(delta).expect("Delta can't convert to interval");
+    let interval: PgInterval = match PgInterval::try_from(delta) {
+        Ok(e) => e,
+        Err(_) => {
+            return Err(Error::Validation(
+                "Puppy can't shift schedules by that much.".to_owned(),
+            ))
+        }
+    };
 
     match ctx
         .data()
@@ -259,16 +482,18 @@ pub async fn shiftschedule(
         .await
     {
         Ok(_) => {
+            ctx.data().wakeup.notify_one();
+
             let response = serenity::MessageBuilder::new()
                 .push("Schedules have been update!!\n")
                 .build();
             ctx.say(response).await?;
             return Ok(());
         }
-        Err(e) => return Err("Server Err".into()),
+        Err(e) => return Err(e.into()),
     };
 }
-#[poise::command(prefix_command, slash_command)]
+#[poise::command(prefix_command, slash_command, guild_only)]
 pub async fn updateuser(
     ctx: Context<'_>,
     #[description = "Only admins can specify other users"] user: Option<serenity::User>,
@@ -283,7 +508,7 @@ pub async fn updateuser(
     #[max = 59_u8]
     timezoneminutes: Option<i8>,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get();
+    let guild = guild_id(ctx)?.get();
     let user_id = match user {
         Some(e) => e.id,
         None => ctx.author().id,
@@ -299,18 +524,16 @@ pub async fn updateuser(
         Err(e) => match e {
             DatabaseErrors::GuildDoesNotExist => {
                 let response = format!("Bark Bark!!!\nI'm new here, please have an admin run /setchannel before adding people.");
-                ctx.say(response).await?;
-                return Ok(());
+                return Err(Error::NotFound(response));
             }
             DatabaseErrors::UserDoesNotExist => {
                 let response = serenity::MessageBuilder::new()
                     .mention(&user_id)
                     .push(" is not my friend yet\nPlease use /adduser to make them my friend!!")
                     .build();
-                ctx.say(response).await?;
-                return Ok(());
+                return Err(Error::NotFound(response));
             }
-            _ => return Err("Server Error".into()),
+            _ => return Err(e.into()),
         },
     };
 
@@ -321,35 +544,26 @@ pub async fn updateuser(
                 .mention(&user_id)
                 .push(" is not my friend yet\nPlease use /adduser to make them my friend!!")
                 .build();
-            ctx.say(response).await?;
-            return Ok(());
+            return Err(Error::NotFound(response));
         }
     };
 
     let interval: PgInterval = match timezonehour {
         Some(h) => match timezoneminutes {
-            Some(m) => {
-                let delta = match h >= 0_i8 {
-                    true => TimeDelta::minutes(m as i64) + TimeDelta::hours(h as i64),
-                    false => TimeDelta::minutes((m - (m * 2)) as i64) + TimeDelta::hours(h as i64),
-                };
-                PgInterval::try_from(delta).expect("Delta can't convert to interval")
-            }
+            Some(m) => timezone_interval(h, m)?,
             None => {
                 let response =
                     "Error: if you are updating the timezone both hour and minutes need to be set";
-                ctx.say(response).await?;
-                return Ok(());
+                return Err(Error::Validation(response.to_owned()));
             }
         },
         None => match timezoneminutes {
             Some(_) => {
                 let response =
                     "Error: if you are updating the timezone both hour and minutes need to be set";
-                ctx.say(response).await?;
-                return Ok(());
+                return Err(Error::Validation(response.to_owned()));
             }
-            None => user_data_old.timezone.clone(),
+            None => user_data_old.timezone,
         },
     };
 
@@ -371,7 +585,6 @@ pub async fn updateuser(
         praise_name: true_praise_name.clone(),
         timezone: interval,
     };
-    println!("testing");
     match ctx.data().db.update_user(&user_data).await {
         Ok(_) => {
             let response = serenity::MessageBuilder::new()
@@ -382,17 +595,18 @@ pub async fn updateuser(
             ctx.say(response).await?;
             return Ok(());
         }
-        Err(e) => return Err("Server Err".into()),
+        Err(e) => return Err(e.into()),
     };
 }
 
 #[poise::command(
     prefix_command,
     slash_command,
+    guild_only,
     default_member_permissions = "ADMINISTRATOR"
 )]
 pub async fn deleteuser(ctx: Context<'_>, user: serenity::User) -> Result<(), Error> {
-    let guild = ctx.guild().unwrap().id.get() as i64;
+    let guild = guild_id(ctx)?.get() as i64;
 
     match ctx
         .data()
@@ -415,45 +629,47 @@ pub async fn deleteuser(ctx: Context<'_>, user: serenity::User) -> Result<(), Er
                     .mention(&user.id)
                     .push(" has alreday gone home.\n")
                     .build();
-                ctx.say(response).await?;
-                Ok(())
-            }
-            _ => {
-                println!("{:?}", e);
-                Err("Server Error".into())
+                Err(Error::NotFound(response))
             }
+            _ => Err(e.into()),
         },
     }
 }
 
-#[poise::command(prefix_command, slash_command)]
-pub async fn addschedule(
-    ctx: Context<'_>,
-    #[description = "Pretence of task"] pretencetask: String,
-    #[description = "Postence of task"] postencetask: String,
-    #[description = "Hour to start tark, will be offset with users timezone"]
-    #[min = 0_i8]
-    #[max = 23_i8]
+/// The options /addschedule and /addscheduleadmin share
+struct ScheduleOptions {
+    pretencetask: String,
+    postencetask: String,
     starthour: i8,
-    #[description = "Minuets start tark, will be offset with users timezone"]
-    #[min = 0_u8]
-    #[max = 59_u8]
     startminuets: i8,
-    #[description = "Add days to interval"]
-    #[min = 1_u8]
-    #[max = 120_u8]
     intervalday: Option<i64>,
-    #[description = "Add hours to interval"]
-    #[min = 1_u8]
-    #[max = 120_u8]
     intervalhour: Option<i64>,
-    #[description = "Add minuets to interval"]
-    #[min = 1_u8]
-    #[max = 120_u8]
     intervalminuets: Option<i64>,
+    extratimes: Option<String>,
+    enddate: Option<String>,
+    maxoccurrences: Option<u32>,
+}
+
+/// Checks the options and adds the task for `user_id`
+async fn add_schedule(
+    ctx: Context<'_>,
+    user_id: serenity::UserId,
+    options: ScheduleOptions,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get();
-    let user_id = ctx.author().id;
+    let guild = guild_id(ctx)?.get();
+
+    let ScheduleOptions {
+        pretencetask,
+        postencetask,
+        starthour,
+        startminuets,
+        intervalday,
+        intervalhour,
+        intervalminuets,
+        extratimes,
+        enddate,
+        maxoccurrences,
+    } = options;
 
     let user_data_opt = match ctx
         .data()
@@ -465,18 +681,16 @@ pub async fn addschedule(
         Err(e) => match e {
             DatabaseErrors::GuildDoesNotExist => {
                 let response = format!("Bark Bark!!!\nI'm new here, please have an admin run /setchannel before adding people.");
-                ctx.say(response).await?;
-                return Ok(());
+                return Err(Error::NotFound(response));
             }
             DatabaseErrors::UserDoesNotExist => {
                 let response = serenity::MessageBuilder::new()
                     .mention(&user_id)
                     .push(" is not my friend yet\nPlease use /adduser to make them my friend!!")
                     .build();
-                ctx.say(response).await?;
-                return Ok(());
+                return Err(Error::NotFound(response));
             }
-            _ => return Err("Server Error".into()),
+            _ => return Err(e.into()),
         },
     };
 
@@ -487,51 +701,68 @@ pub async fn addschedule(
                 .mention(&user_id)
                 .push(" is not my friend yet\nPlease use /adduser to make them my friend!!")
                 .build();
-            ctx.say(response).await?;
-            return Ok(());
+            return Err(Error::NotFound(response));
         }
     };
 
-    let mut duration = chrono::Duration::zero();
-
-    match intervalday {
-        Some(e) => duration = duration + chrono::TimeDelta::days(e),
-        None => (),
-    };
-
-    match intervalhour {
-        Some(e) => duration = duration + chrono::TimeDelta::hours(e),
-        None => (),
-    };
-    match intervalminuets {
-        Some(e) => duration = duration + chrono::TimeDelta::minutes(e),
-        None => (),
+    let duration = match crate::util::schedule_interval(intervalday, intervalhour, intervalminuets)
+    {
+        Some(e) => e,
+        None => {
+            let res = "Puppy can only count 1 to 120 days, hours or minutes for an interval.";
+            return Err(Error::Validation(res.to_owned()));
+        }
     };
 
     if duration < chrono::TimeDelta::hours(4) {
         let res = "Puppy can only bark every 4 hours.\nPlease set the interval to atleast 4 hours.";
-        ctx.say(res).await?;
-        return Ok(());
+        return Err(Error::Validation(res.to_owned()));
     }
 
-    let duration = PgInterval::try_from(duration).expect("Cannot conver delta into pginterval");
+    let start = match NaiveTime::from_hms_opt(starthour as u32, startminuets as u32, 0) {
+        Some(e) => e,
+        None => {
+            return Err(Error::Validation(
+                "Puppy doesn't know that time.".to_owned(),
+            ));
+        }
+    };
+
+    let mut times = crate::util::times_with_extra(extratimes.as_deref(), start, &duration)?;
 
-    let now = Utc::now();
+    let end_date = match enddate {
+        Some(d) => match chrono::NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d") {
+            Ok(e) => Some(end_of_local_day(e, &user_data.timezone)?),
+            Err(_) => {
+                let res = "Puppy doesn't understand that date.\nPlease write it like 2025-03-01";
+                return Err(Error::Validation(res.to_owned()));
+            }
+        },
+        None => None,
+    };
 
-    let time_string = format!(
-        "{} {}:{}:00 +0000",
-        now.date_naive().to_string(),
-        starthour,
-        startminuets
-    );
+    let duration = match PgInterval::try_from(duration) {
+        Ok(e) => e,
+        Err(_) => {
+            let res = "Puppy can't count an interval that long.";
+            return Err(Error::Validation(res.to_owned()));
+        }
+    };
+
+    let now = ctx.data().clock.now();
 
-    let mut datetime =
-        DateTime::parse_from_str(&time_string, "%F %T %z").expect("Cannot parse datetime");
+    // A single time of day is just where the interval starts from
+    if times.len() <= 1 {
+        times.clear();
+    }
 
-    datetime = datetime - TimeDelta::microseconds(user_data.timezone.microseconds);
+    let datetime = crate::util::first_run(now, &user_data.timezone, start, &times);
 
-    if datetime < now {
-        datetime = datetime + TimeDelta::days(1);
+    if let Some(end) = end_date {
+        if datetime >= end {
+            let res = "That end date is before puppy's first reminder!!";
+            return Err(Error::Validation(res.to_owned()));
+        }
     }
 
     let user_delta = TimeDelta::microseconds(user_data.timezone.microseconds);
@@ -547,11 +778,17 @@ pub async fn addschedule(
             task_secondary: postencetask,
             created: now,
             interval: duration,
-            next_run: datetime.to_utc(),
+            times,
+            next_run: datetime,
+            end_date,
+            max_runs: maxoccurrences.map(|m| m.min(i32::MAX as u32) as i32),
+            runs: 0,
         })
         .await
     {
         Ok(e) => {
+            ctx.data().wakeup.notify_one();
+
             let response = serenity::MessageBuilder::new()
                 .push("Puppy will remember a new task for ")
                 .mention(&user_id)
@@ -560,23 +797,92 @@ pub async fn addschedule(
                     e.task,
                     (e.next_run + user_delta).naive_utc(),
                     crate::util::format_timezone(&user_data.timezone),
-                    crate::util::pginterval_to_string(&duration)
+                    crate::util::schedule_to_string(&duration, &e.times)
                 ))
+                .push(
+                    match crate::util::end_to_string(
+                        e.runs,
+                        e.max_runs,
+                        e.end_date,
+                        &user_data.timezone,
+                    ) {
+                        Some(end) => format!("\nThis task {}", end),
+                        None => String::new(),
+                    },
+                )
                 .build();
             ctx.say(response).await?;
             return Ok(());
         }
-        Err(_) => {
-            return Err("Database error".into());
+        Err(e) => {
+            return Err(e.into());
         }
     };
 }
 
+#[poise::command(prefix_command, slash_command, guild_only)]
+// Every option is its own slash command parameter
+#[allow(clippy::too_many_arguments)]
+pub async fn addschedule(
+    ctx: Context<'_>,
+    #[description = "Pretence of task"] pretencetask: String,
+    #[description = "Postence of task"] postencetask: String,
+    #[description = "Hour to start tark, will be offset with users timezone"]
+    #[min = 0_i8]
+    #[max = 23_i8]
+    starthour: i8,
+    #[description = "Minuets start tark, will be offset with users timezone"]
+    #[min = 0_u8]
+    #[max = 59_u8]
+    startminuets: i8,
+    #[description = "Add days to interval"]
+    #[min = 1_u8]
+    #[max = 120_u8]
+    intervalday: Option<i64>,
+    #[description = "Add hours to interval"]
+    #[min = 1_u8]
+    #[max = 120_u8]
+    intervalhour: Option<i64>,
+    #[description = "Add minuets to interval"]
+    #[min = 1_u8]
+    #[max = 120_u8]
+    intervalminuets: Option<i64>,
+    #[description = "Extra times of day, e.g. 14:00, 20:00. Needs a whole day interval"]
+    extratimes: Option<String>,
+    #[description = "Last day to remind on, e.g. 2025-03-01"] enddate: Option<String>,
+    #[description = "Stop after this many reminders"]
+    #[min = 1_u32]
+    maxoccurrences: Option<u32>,
+) -> Result<(), Error> {
+    let user_id = ctx.author().id;
+
+    add_schedule(
+        ctx,
+        user_id,
+        ScheduleOptions {
+            pretencetask,
+            postencetask,
+            starthour,
+            startminuets,
+            intervalday,
+            intervalhour,
+            intervalminuets,
+            extratimes,
+            enddate,
+            maxoccurrences,
+        },
+    )
+    .await
+}
+
 #[poise::command(
     prefix_command,
     slash_command,
+    guild_only,
     default_member_permissions = "ADMINISTRATOR"
 )]
+// Every option is its own slash command parameter
+#[allow(clippy::too_many_arguments)]
 pub async fn addscheduleadmin(
     ctx: Context<'_>,
     #[description = "Pretence of task"] pretencetask: String,
@@ -601,14 +907,110 @@ pub async fn addscheduleadmin(
     #[min = 1_u8]
     #[max = 120_u8]
     intervalminuets: Option<i64>,
+    #[description = "Extra times of day, e.g. 14:00, 20:00. Needs a whole day interval"]
+    extratimes: Option<String>,
+    #[description = "Last day to remind on, e.g. 2025-03-01"] enddate: Option<String>,
+    #[description = "Stop after this many reminders"]
+    #[min = 1_u32]
+    maxoccurrences: Option<u32>,
     user: Option<serenity::User>,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get();
     let user_id = match user {
         Some(e) => e.id,
         None => ctx.author().id,
     };
 
+    add_schedule(
+        ctx,
+        user_id,
+        ScheduleOptions {
+            pretencetask,
+            postencetask,
+            starthour,
+            startminuets,
+            intervalday,
+            intervalhour,
+            intervalminuets,
+            extratimes,
+            enddate,
+            maxoccurrences,
+        },
+    )
+    .await
+}
+
+/// Works out the task for a parsed reminder as if it was added at `now`
+fn reminder_task(
+    parsed: &crate::parser::ParsedTask,
+    guild: i64,
+    user: &User,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Task, Error> {
+    let interval = match PgInterval::try_from(parsed.interval) {
+        Ok(e) => e,
+        Err(_) => {
+            return Err(Error::Validation(
+                "Puppy can't count that high!!".to_owned(),
+            ));
+        }
+    };
+
+    let next_run = match parsed
+        .times
+        .iter()
+        .map(|t| crate::util::next_local_time(now, &user.timezone, *t))
+        .min()
+    {
+        Some(e) => e,
+        None => now + parsed.interval,
+    };
+
+    // A single time of day is just where the interval starts from
+    let times = match parsed.times.len() > 1 {
+        true => parsed.times.clone(),
+        false => Vec::new(),
+    };
+
+    let end_date = match (parsed.duration, parsed.until) {
+        (Some(d), _) => Some(now + d),
+        (None, Some(d)) => Some(end_of_local_day(d, &user.timezone)?),
+        (None, None) => None,
+    };
+
+    if let Some(end) = end_date {
+        if next_run >= end {
+            return Err(Error::Validation(
+                "That end date is before puppy's first reminder!!".to_owned(),
+            ));
+        }
+    }
+
+    Ok(Task {
+        id: 0,
+        guild_id: guild,
+        user_id: user.id,
+        task: parsed.task.clone(),
+        task_secondary: parsed.task_secondary.clone(),
+        created: now,
+        interval,
+        times,
+        next_run,
+        end_date,
+        max_runs: parsed.max_runs,
+        runs: 0,
+    })
+}
+
+#[poise::command(prefix_command, slash_command, guild_only)]
+pub async fn remind(
+    ctx: Context<'_>,
+    #[description = "What and when, e.g. take meds every day at 9am and 9pm"]
+    #[rest]
+    reminder: String,
+) -> Result<(), Error> {
+    let guild = guild_id(ctx)?.get();
+    let user_id = ctx.author().id;
+
     let user_data_opt = match ctx
         .data()
         .db
@@ -616,22 +1018,7 @@ pub async fn addscheduleadmin(
         .await
     {
         Ok(e) => e,
-        Err(e) => match e {
-            DatabaseErrors::GuildDoesNotExist => {
-                let response = format!("Bark Bark!!!\nI'm new here, please have an admin run /setchannel before adding people.");
-                ctx.say(response).await?;
-                return Ok(());
-            }
-            DatabaseErrors::UserDoesNotExist => {
-                let response = serenity::MessageBuilder::new()
-                    .mention(&user_id)
-                    .push(" is not my friend yet\nPlease use /adduser to make them my friend!!")
-                    .build();
-                ctx.say(response).await?;
-                return Ok(());
-            }
-            _ => return Err("Server Error".into()),
-        },
+        Err(e) => return Err(e.into()),
     };
 
     let user_data = match user_data_opt {
@@ -641,102 +1028,188 @@ pub async fn addscheduleadmin(
                 .mention(&user_id)
                 .push(" is not my friend yet\nPlease use /adduser to make them my friend!!")
                 .build();
-            ctx.say(response).await?;
-            return Ok(());
+            return Err(Error::NotFound(response));
         }
     };
 
-    let mut duration = chrono::Duration::zero();
-
-    match intervalday {
-        Some(e) => duration = duration + chrono::TimeDelta::days(e),
-        None => (),
+    let parsed = match crate::parser::parse_reminders(&reminder) {
+        Ok(e) => e,
+        Err(e) => return Err(Error::Validation(e.to_string())),
     };
 
-    match intervalhour {
-        Some(e) => duration = duration + chrono::TimeDelta::hours(e),
-        None => (),
-    };
-    match intervalminuets {
-        Some(e) => duration = duration + chrono::TimeDelta::minutes(e),
-        None => (),
-    };
+    let now = ctx.data().clock.now();
 
-    if duration < chrono::TimeDelta::hours(4) {
-        let res = "Puppy can only bark every 4 hours.\nPlease set the interval to atleast 4 hours.";
-        ctx.say(res).await?;
-        return Ok(());
-    }
+    let mut preview = serenity::MessageBuilder::new();
 
-    let duration = PgInterval::try_from(duration).expect("Cannot conver delta into pginterval");
+    for p in &parsed {
+        let task = reminder_task(p, guild as i64, &user_data, now)?;
 
-    let now = Utc::now();
+        preview.push("Puppy will remind ");
+        preview.mention(&user_id);
+        preview.push(" to ");
+        preview.push_bold(task.task.clone());
+        preview.push(format!(
+            " every {}\nThe next reminders will be at ",
+            crate::util::schedule_to_string(&task.interval, &task.times)
+        ));
 
-    let time_string = format!(
-        "{} {}:{}:00 +0000",
-        now.date_naive().to_string(),
-        starthour,
-        startminuets
-    );
+        let mut upcoming = vec![task.next_run];
+        while upcoming.len() < 3 {
+            let last = upcoming[upcoming.len() - 1];
+            upcoming.push(crate::util::next_occurrence(
+                &last,
+                &task.interval,
+                &task.times,
+                &user_data.timezone,
+            ));
+        }
 
-    let mut datetime =
-        DateTime::parse_from_str(&time_string, "%F %T %z").expect("Cannot parse datetime");
+        if let Some(end) = task.end_date {
+            upcoming.retain(|e| *e < end);
+        }
 
-    datetime = datetime - TimeDelta::microseconds(user_data.timezone.microseconds);
+        if let Some(max) = task.max_runs {
+            upcoming.truncate(max as usize);
+        }
 
-    if datetime < now {
-        datetime = datetime + TimeDelta::days(1);
+        preview.push(
+            upcoming
+                .iter()
+                .map(|e| crate::util::format_local(e, &user_data.timezone))
+                .collect::<Vec<String>>()
+                .join(", "),
+        );
+        preview.push("\n");
+
+        if let Some(end) =
+            crate::util::end_to_string(0, task.max_runs, task.end_date, &user_data.timezone)
+        {
+            preview.push(format!("This task {}\n", end));
+        }
     }
 
-    let user_delta = TimeDelta::microseconds(user_data.timezone.microseconds);
-
-    match ctx
-        .data()
-        .db
-        .add_task(Task {
-            id: 0,
-            guild_id: guild as i64,
-            user_id: user_data.id,
-            task: pretencetask,
-            task_secondary: postencetask,
-            created: now,
-            interval: duration,
-            next_run: datetime.to_utc(),
-        })
-        .await
-    {
-        Ok(e) => {
-            let response = serenity::MessageBuilder::new()
-                .push("Puppy will remember a new task for ")
-                .mention(&user_id)
-                .push(format!(
-                    "\nPuppy will remind them to {} starting from {} {} every {}",
-                    e.task,
-                    (e.next_run + user_delta).naive_utc(),
-                    crate::util::format_timezone(&user_data.timezone),
-                    crate::util::pginterval_to_string(&duration)
-                ))
-                .build();
-            ctx.say(response).await?;
+    preview.push("Does that sound right?");
+
+    let confirm_id = format!("{}-confirm", ctx.id());
+    let cancel_id = format!("{}-cancel", ctx.id());
+
+    let reply = poise::CreateReply::default()
+        .content(preview.build())
+        .components(vec![serenity::CreateActionRow::Buttons(vec![
+            serenity::CreateButton::new(confirm_id.clone())
+                .style(serenity::ButtonStyle::Success)
+                .label("Yes"),
+            serenity::CreateButton::new(cancel_id.clone())
+                .style(serenity::ButtonStyle::Danger)
+                .label("No"),
+        ])]);
+
+    let handle = ctx.send(reply).await?;
+
+    let confirm_filter = confirm_id.clone();
+    let interaction = serenity::ComponentInteractionCollector::new(ctx)
+        .author_id(user_id)
+        .channel_id(ctx.channel_id())
+        .timeout(std::time::Duration::from_secs(120))
+        .filter(move |mci| mci.data.custom_id == confirm_filter || mci.data.custom_id == cancel_id)
+        .await;
+
+    let interaction = match interaction {
+        Some(e) if e.data.custom_id == confirm_id => e,
+        Some(e) => {
+            e.create_response(
+                ctx,
+                serenity::CreateInteractionResponse::UpdateMessage(
+                    serenity::CreateInteractionResponseMessage::new()
+                        .content("Okay, puppy won't remember that one.")
+                        .components(vec![]),
+                ),
+            )
+            .await?;
             return Ok(());
         }
-        Err(_) => {
-            return Err("Database error".into());
+        None => {
+            handle
+                .edit(
+                    ctx,
+                    poise::CreateReply::default()
+                        .content("Puppy got bored waiting and forgot about it.")
+                        .components(vec![]),
+                )
+                .await?;
+            return Ok(());
         }
     };
+
+    // The preview could have sat there for a while, start from when it was confirmed
+    let now = ctx.data().clock.now();
+
+    let tasks = parsed
+        .iter()
+        .map(|p| reminder_task(p, guild as i64, &user_data, now))
+        .collect::<Result<Vec<Task>, Error>>()?;
+
+    let mut response = serenity::MessageBuilder::new();
+
+    for task in tasks {
+        match ctx.data().db.add_task(task).await {
+            Ok(e) => {
+                response
+                    .push("Puppy will remember a new task for ")
+                    .mention(&user_id)
+                    .push(format!(
+                        "\nPuppy will remind them to {} starting from {} every {}",
+                        e.task,
+                        crate::util::format_local(&e.next_run, &user_data.timezone),
+                        crate::util::schedule_to_string(&e.interval, &e.times)
+                    ))
+                    .push(
+                        match crate::util::end_to_string(
+                            e.runs,
+                            e.max_runs,
+                            e.end_date,
+                            &user_data.timezone,
+                        ) {
+                            Some(end) => format!("\nThis task {}", end),
+                            None => String::new(),
+                        },
+                    )
+                    .push("\n");
+            }
+            Err(e) => return Err(e.into()),
+        };
+    }
+
+    let response = response.build();
+
+    ctx.data().wakeup.notify_one();
+
+    interaction
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::UpdateMessage(
+                serenity::CreateInteractionResponseMessage::new()
+                    .content(response)
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+
+    Ok(())
 }
 
 #[poise::command(
     prefix_command,
     slash_command,
+    guild_only,
     default_member_permissions = "ADMINISTRATOR"
 )]
 pub async fn getscheduleall(ctx: Context<'_>) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get() as i64;
+    let guild = guild_id(ctx)?.get() as i64;
 
     let tasks = match ctx.data().db.get_task_guild(&guild).await {
         Ok(e) => e,
-        Err(_) => return Err("Database error".into()),
+        Err(e) => return Err(e.into()),
     };
 
     let res = generate_task_table(&tasks);
@@ -745,14 +1218,14 @@ pub async fn getscheduleall(ctx: Context<'_>) -> Result<(), Error> {
     return Ok(());
 }
 
-#[poise::command(prefix_command, slash_command)]
+#[poise::command(prefix_command, slash_command, guild_only)]
 pub async fn getschedule(ctx: Context<'_>) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get() as i64;
+    let guild = guild_id(ctx)?.get() as i64;
     let user = ctx.author().id.get() as i64;
 
     let tasks = match ctx.data().db.get_task_user(&guild, &user).await {
         Ok(e) => e,
-        Err(_) => return Err("Database error".into()),
+        Err(e) => return Err(e.into()),
     };
 
     let res = generate_task_table(&tasks);
@@ -764,10 +1237,11 @@ pub async fn getschedule(ctx: Context<'_>) -> Result<(), Error> {
 #[poise::command(
     prefix_command,
     slash_command,
+    guild_only,
     default_member_permissions = "ADMINISTRATOR"
 )]
 pub async fn getscheduleadmin(ctx: Context<'_>, user: Option<serenity::User>) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get() as i64;
+    let guild = guild_id(ctx)?.get() as i64;
     let user_id = match user {
         Some(e) => e.id.get(),
         None => ctx.author().id.get(),
@@ -775,7 +1249,7 @@ pub async fn getscheduleadmin(ctx: Context<'_>, user: Option<serenity::User>) ->
 
     let tasks = match ctx.data().db.get_task_user(&guild, &user_id).await {
         Ok(e) => e,
-        Err(_) => return Err("Database error".into()),
+        Err(e) => return Err(e.into()),
     };
 
     let res = generate_task_table(&tasks);
@@ -784,100 +1258,308 @@ pub async fn getscheduleadmin(ctx: Context<'_>, user: Option<serenity::User>) ->
     return Ok(());
 }
 
-#[poise::command(prefix_command, slash_command)]
+#[poise::command(prefix_command, slash_command, guild_only)]
 pub async fn deleteschedule(
     ctx: Context<'_>,
-    #[description = "ID from of task /getschedule"] id: u32,
+    #[description = "ID from of task /getschedule"]
+    #[autocomplete = "autocomplete_task_user"]
+    id: u32,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get() as i64;
+    let guild = guild_id(ctx)?.get() as i64;
     let user_id = ctx.author().id;
 
     let task_opt = match ctx.data().db.get_task_id(&(id as i64)).await {
         Ok(e) => e,
-        Err(_) => return Err("Database error".into()),
+        Err(e) => return Err(e.into()),
     };
 
     let task = match task_opt {
         Some(e) => e,
         None => {
             let res = "Puppy doesn't remember this task.\nPlase make a task for puppy to remeber with /addschedule";
-            ctx.say(res).await?;
-            return Ok(());
+            return Err(Error::NotFound(res.to_owned()));
         }
     };
 
     if task.guild_id != guild {
         let res = "Puppy doesn't remember this task.\nPlase make a task for puppy to remeber with /addschedule";
-        ctx.say(res).await?;
-        return Ok(());
+        return Err(Error::NotFound(res.to_owned()));
     }
 
     let user = match ctx.data().db.get_user_id(&task.user_id).await {
         Ok(e) => match e {
             Some(u) => u,
-            None => return Err("There is a missing user to a task".into()),
+            None => {
+                return Err(Error::NotFound(
+                    "Puppy can't find who this task belongs to".to_owned(),
+                ))
+            }
         },
-        Err(_) => return Err("Database Error".into()),
+        Err(e) => return Err(e.into()),
     };
 
     if user_id != (user.user_id as u64) {
         let res = serenity::MessageBuilder::new()
-            .push("Hey you don't smell like the owner!!\nPlase  have ")
+            .push("Hey you don't smell like the owner!!\nPlease have ")
             .mention(&serenity::UserId::new(user.user_id as u64))
             .push(" delete this task")
             .build();
-        ctx.say(res).await?;
-        return Ok(());
+        return Err(Error::Permission(res));
     }
 
     match ctx.data().db.delete_task(&(id as i64)).await {
         Ok(_) => {
-            let res = "Bark Bark!!!\nPuppy has forgotten the task!!!";
+            let res = format!(
+                "Bark Bark!!!\nPuppy has forgotten the task!!!\nIf that was a mistake use /restoreschedule within {} to bring it back",
+                crate::util::timedelta_to_string(&ctx.data().archive_retention)
+            );
             ctx.say(res).await?;
             return Ok(());
         }
-        Err(_) => return Err("Database Error".into()),
+        Err(e) => return Err(e.into()),
     };
 }
 
 #[poise::command(
     prefix_command,
     slash_command,
+    guild_only,
     default_member_permissions = "ADMINISTRATOR"
 )]
 pub async fn deletescheduleadmin(
     ctx: Context<'_>,
-    #[description = "ID from of task /getschedule"] id: u32,
+    #[description = "ID from of task /getschedule"]
+    #[autocomplete = "autocomplete_task_admin"]
+    id: u32,
+    // Discord wants required options first, autocomplete still sees it once it's filled in
+    #[description = "Only list tasks for this user"] user: Option<serenity::User>,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().expect("not buing used in guild").id.get() as i64;
-    let user_id = ctx.author().id;
+    let guild = guild_id(ctx)?.get() as i64;
 
     let task_opt = match ctx.data().db.get_task_id(&(id as i64)).await {
         Ok(e) => e,
-        Err(_) => return Err("Database error".into()),
+        Err(e) => return Err(e.into()),
     };
 
     let task = match task_opt {
         Some(e) => e,
         None => {
             let res = "Puppy doesn't remember this task.\nPlase make a task for puppy to remeber with /addschedule";
-            ctx.say(res).await?;
-            return Ok(());
+            return Err(Error::NotFound(res.to_owned()));
         }
     };
 
     if task.guild_id != guild {
         let res = "Puppy doesn't remember this task.\nPlase make a task for puppy to remeber with /addschedule";
-        ctx.say(res).await?;
-        return Ok(());
+        return Err(Error::NotFound(res.to_owned()));
+    }
+
+    if let Some(u) = user {
+        let owner = match ctx.data().db.get_user_id(&task.user_id).await {
+            Ok(e) => match e {
+                Some(o) => o,
+                None => {
+                    return Err(Error::NotFound(
+                        "Puppy can't find who this task belongs to".to_owned(),
+                    ))
+                }
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        if owner.user_id != (u.id.get() as i64) {
+            let res = serenity::MessageBuilder::new()
+                .push("Puppy can't find that task for ")
+                .mention(&u.id)
+                .push("\nPlease pick one of their tasks from the list")
+                .build();
+            return Err(Error::NotFound(res));
+        }
     }
 
     match ctx.data().db.delete_task(&(id as i64)).await {
         Ok(_) => {
-            let res = "Bark Bark!!!\nPuppy has forgotten the task!!!";
+            let res = format!(
+                "Bark Bark!!!\nPuppy has forgotten the task!!!\nIf that was a mistake use /restoreschedule within {} to bring it back",
+                crate::util::timedelta_to_string(&ctx.data().archive_retention)
+            );
             ctx.say(res).await?;
             return Ok(());
         }
-        Err(_) => return Err("Database Error".into()),
+        Err(e) => return Err(e.into()),
+    };
+}
+
+/// Restores an archived task if it was deleted and hasn't been purged yet
+async fn restore_task(ctx: Context<'_>, task: ArchivedTask) -> Result<(), Error> {
+    let paused = match task.reason.as_str() {
+        "deleted" => None,
+        PAUSED_MEMBER_LEFT => Some("its owner comes back to the server"),
+        PAUSED_GUILD_REMOVED => Some("puppy is added back to the server"),
+        PAUSED_CHANNEL_DELETED => Some("an admin sets puppy's channel with /setchannel"),
+        _ => {
+            let res = "Puppy already finished this task!!\nPlease make a new one with /addschedule";
+            return Err(Error::Validation(res.to_owned()));
+        }
     };
+
+    if let Some(returns) = paused {
+        let res = format!(
+            "Puppy only paused this task!!\nIt will come back on its own when {}",
+            returns
+        );
+        return Err(Error::Validation(res));
+    }
+
+    if let Some(end) = task.end_date {
+        if end <= ctx.data().clock.now() {
+            let res =
+                "This task has already ended.\nPlease make a new one with /addschedule".to_owned();
+            return Err(Error::Validation(res));
+        }
+    }
+
+    match ctx
+        .data()
+        .db
+        .restore_task(&task.id, &ctx.data().clock.now())
+        .await
+    {
+        Ok(e) => {
+            ctx.data().wakeup.notify_one();
+
+            let res = serenity::MessageBuilder::new()
+                .push("Bark Bark!!!\nPuppy remembers ")
+                .push_bold(e.task.clone())
+                .push(" again for ")
+                .mention(&serenity::UserId::new(task.user_id as u64))
+                .push(format!(
+                    "!!!\nThe next reminder will be {}",
+                    crate::util::format_local(&e.next_run, &task.timezone)
+                ))
+                .build();
+            ctx.say(res).await?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Marks a reminder as done for people who can't easily react to it
+#[poise::command(prefix_command, slash_command, guild_only)]
+pub async fn done(
+    ctx: Context<'_>,
+    #[description = "Only mark this task as done"]
+    #[autocomplete = "autocomplete_task_user"]
+    task: Option<u32>,
+) -> Result<(), Error> {
+    let guild = guild_id(ctx)?.get() as i64;
+    let user_id = ctx.author().id;
+
+    let occurrence = crate::repo::schedule::Scheduler::oldest_unacknowledged(
+        &ctx.data().active_messages,
+        guild,
+        user_id.get() as i64,
+        task.map(|e| e as i64),
+    )
+    .await;
+
+    let (channel, reminder) = match occurrence {
+        Some(e) => e,
+        None => {
+            let res = "Puppy isn't waiting on anything from you right now!!";
+            return Err(Error::NotFound(res.to_owned()));
+        }
+    };
+
+    crate::repo::schedule::Scheduler::mark_done(
+        &ctx.data().messenger,
+        &ctx.data().active_messages,
+        channel,
+        reminder,
+        user_id.get(),
+        ctx.data().clock.now(),
+    )
+    .await;
+
+    let res = serenity::MessageBuilder::new()
+        .push("Bark Bark!!! Puppy has marked ")
+        .push(serenity::MessageId::new(reminder).link(
+            serenity::ChannelId::new(channel as u64),
+            Some(serenity::GuildId::new(guild as u64)),
+        ))
+        .push(" as done")
+        .build();
+    ctx.say(res).await?;
+    Ok(())
+}
+
+#[poise::command(prefix_command, slash_command, guild_only)]
+pub async fn restoreschedule(
+    ctx: Context<'_>,
+    #[description = "ID of a deleted task"]
+    #[autocomplete = "autocomplete_deleted_user"]
+    id: u32,
+) -> Result<(), Error> {
+    let guild = guild_id(ctx)?.get() as i64;
+    let user_id = ctx.author().id;
+
+    let task = match ctx.data().db.get_archived_task_id(&(id as i64)).await {
+        Ok(Some(e)) if e.guild_id == guild => e,
+        Ok(_) => {
+            let res = "Puppy can't find that task, it may have been forgotten forever.";
+            return Err(Error::NotFound(res.to_owned()));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if user_id != (task.user_id as u64) {
+        let res = serenity::MessageBuilder::new()
+            .push("Hey you don't smell like the owner!!\nPlease have ")
+            .mention(&serenity::UserId::new(task.user_id as u64))
+            .push(" restore this task")
+            .build();
+        return Err(Error::Permission(res));
+    }
+
+    restore_task(ctx, task).await
+}
+
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR"
+)]
+pub async fn restorescheduleadmin(
+    ctx: Context<'_>,
+    #[description = "ID of a deleted task"]
+    #[autocomplete = "autocomplete_deleted_admin"]
+    id: u32,
+    // Discord wants required options first, autocomplete still sees it once it's filled in
+    #[description = "Only list tasks for this user"] user: Option<serenity::User>,
+) -> Result<(), Error> {
+    let guild = guild_id(ctx)?.get() as i64;
+
+    let task = match ctx.data().db.get_archived_task_id(&(id as i64)).await {
+        Ok(Some(e)) if e.guild_id == guild => e,
+        Ok(_) => {
+            let res = "Puppy can't find that task, it may have been forgotten forever.";
+            return Err(Error::NotFound(res.to_owned()));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(u) = user {
+        if task.user_id != (u.id.get() as i64) {
+            let res = serenity::MessageBuilder::new()
+                .push("Puppy can't find that task for ")
+                .mention(&u.id)
+                .push("\nPlease pick one of their tasks from the list")
+                .build();
+            return Err(Error::NotFound(res));
+        }
+    }
+
+    restore_task(ctx, task).await
 }
@@ -0,0 +1,99 @@
+use poise::serenity_prelude as serenity;
+
+use crate::repo::storage::DatabaseErrors;
+
+/// Everything a command can fail with. Validation, not found and permission
+/// errors carry the message to show the user, storage and discord errors keep
+/// their cause so it can be logged.
+#[derive(Debug)]
+pub enum Error {
+    /// The user gave puppy something it can't use
+    Validation(String),
+    /// Something the command refers to doesn't exist
+    NotFound(String),
+    /// The user isn't allowed to do this
+    Permission(String),
+    Storage(DatabaseErrors),
+    Discord(Box<serenity::Error>),
+}
+
+impl Error {
+    /// What to tell the user, storage and discord problems aren't theirs to fix
+    /// so they only get an apology
+    pub fn user_message(&self) -> String {
+        match self {
+            Error::Validation(e) | Error::NotFound(e) | Error::Permission(e) => e.clone(),
+            Error::Storage(_) => {
+                "Puppy dropped their notes and can't do that right now, please try again later"
+                    .to_owned()
+            }
+            Error::Discord(_) => {
+                "Puppy couldn't get through to Discord, please try again later".to_owned()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Validation(e) => write!(f, "invalid input: {}", e),
+            Error::NotFound(e) => write!(f, "not found: {}", e),
+            Error::Permission(e) => write!(f, "not allowed: {}", e),
+            Error::Storage(_) => write!(f, "storage error"),
+            Error::Discord(_) => write!(f, "discord error"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Storage(e) => Some(e),
+            Error::Discord(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<DatabaseErrors> for Error {
+    fn from(e: DatabaseErrors) -> Error {
+        Error::Storage(e)
+    }
+}
+
+impl From<serenity::Error> for Error {
+    fn from(e: serenity::Error) -> Error {
+        Error::Discord(Box::new(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::error::Error as _;
+
+    #[test]
+    fn storage_error_keeps_its_cause() {
+        let error = Error::from(DatabaseErrors::Query(sqlx::Error::RowNotFound));
+
+        assert!(!error.user_message().contains("no rows"));
+        assert_eq!(error.source().unwrap().to_string(), "query failed");
+        assert!(error
+            .source()
+            .unwrap()
+            .source()
+            .unwrap()
+            .to_string()
+            .contains("no rows"));
+    }
+
+    #[test]
+    fn user_errors_are_shown_as_is() {
+        let error = Error::Validation("Puppy doesn't know that time.".to_owned());
+
+        assert_eq!(error.user_message(), "Puppy doesn't know that time.");
+        assert!(error.source().is_none());
+    }
+}
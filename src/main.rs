@@ -1,6 +1,11 @@
 #![warn(clippy::str_to_string)]
 
+mod clock;
 mod commands;
+mod error;
+mod messenger;
+mod monitoring;
+mod parser;
 mod repo;
 mod util;
 use dotenvy::dotenv;
@@ -15,14 +20,28 @@ use std::{
     time::Duration,
 };
 
-use crate::repo::database::Database;
+use tracing_subscriber::EnvFilter;
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::Error;
+use crate::messenger::{ChannelCache, Messenger, SerenityMessenger};
+use crate::repo::storage::Storage;
 
 struct Data {
-    pub db: repo::database::Database,
+    pub db: Arc<dyn Storage>,
     pub active_messages: Arc<tokio::sync::Mutex<HashMap<u64, repo::schedule::Message>>>,
+    /// How long deleted schedules are kept before being purged
+    pub archive_retention: chrono::TimeDelta,
+    pub clock: Arc<dyn Clock>,
+    pub messenger: Arc<dyn Messenger>,
+    /// Wakes the scheduler early when a command changes when a task runs
+    pub wakeup: Arc<tokio::sync::Notify>,
+    /// Shared with the messengers so channel events can invalidate it
+    pub channels: Arc<ChannelCache>,
+    /// Name this instance claims and sends deliveries under
+    pub instance: String,
 }
 
-type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
 
 async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
@@ -32,20 +51,71 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
     match error {
         poise::FrameworkError::Setup { error, .. } => panic!("Failed to start bot: {:?}", error),
         poise::FrameworkError::Command { error, ctx, .. } => {
-            println!("Error in command `{}`: {:?}", ctx.command().name, error,);
+            let mut causes = Vec::new();
+            let mut cause = std::error::Error::source(&error);
+            while let Some(e) = cause {
+                causes.push(e.to_string());
+                cause = e.source();
+            }
+
+            match error {
+                // Puppy's own problems, the rest is just the user getting it wrong
+                Error::Storage(_) | Error::Discord(_) => tracing::error!(
+                    command = %ctx.command().qualified_name,
+                    error = %error,
+                    caused_by = %causes.join(": "),
+                    "command failed"
+                ),
+                _ => tracing::info!(
+                    command = %ctx.command().qualified_name,
+                    error = %error,
+                    "command refused"
+                ),
+            };
+
+            let reply = poise::CreateReply::default()
+                .content(error.user_message())
+                .ephemeral(true);
+
+            if let Err(e) = ctx.send(reply).await {
+                tracing::warn!(
+                    command = %ctx.command().qualified_name,
+                    error = %e,
+                    "cannot tell user about error"
+                );
+            }
         }
         error => {
             if let Err(e) = poise::builtins::on_error(error).await {
-                println!("Error while handling error: {}", e)
+                tracing::error!(error = %e, "error while handling error")
             }
         }
     }
 }
 
+/// Logs go to stdout. RUST_LOG picks what gets logged, e.g.
+/// `reminderpup::repo::schedule=debug`, and LOG_FORMAT=json writes one json
+/// object per line for log collectors.
+fn init_tracing() {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(e) => e,
+        Err(_) => EnvFilter::new("warn,reminderpup=info"),
+    };
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match var("LOG_FORMAT").as_deref() {
+        Ok("json") => subscriber.json().init(),
+        _ => subscriber.init(),
+    };
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     dotenv().expect(".env file not found");
 
+    init_tracing();
+
     if env::var("DATABASE_URL").is_err() {
         panic!("DATABASE_URL not in environment vars");
     }
@@ -56,13 +126,46 @@ async fn main() {
         panic!("DATABASE_URL not in environment vars");
     }
 
-    let db = Database::new(env::var("DATABASE_URL").unwrap())
+    let db: Arc<dyn Storage> = repo::connect(env::var("DATABASE_URL").unwrap())
         .await
         .expect("Cannot start database");
 
+    let archive_retention = chrono::TimeDelta::days(match env::var("ARCHIVE_RETENTION_DAYS") {
+        Ok(e) => e
+            .parse()
+            .expect("ARCHIVE_RETENTION_DAYS needs to be a number of days"),
+        Err(_) => 30,
+    });
+
+    // Prometheus scrapes /metrics here when it's set, keep it off public
+    // interfaces. Puppy carries on without metrics rather than not starting.
+    if let Ok(addr) = env::var("METRICS_ADDR") {
+        match addr.parse() {
+            Ok(e) => match monitoring::install(e) {
+                Ok(_) => tracing::info!(addr = %e, "serving metrics"),
+                Err(err) => {
+                    tracing::error!(addr = %e, error = %err, "cannot start metrics endpoint")
+                }
+            },
+            Err(_) => tracing::error!(
+                addr = %addr,
+                "METRICS_ADDR needs to be an address like 127.0.0.1:9185, not serving metrics"
+            ),
+        };
+    }
+
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+
     let active_messages: Arc<tokio::sync::Mutex<HashMap<u64, repo::schedule::Message>>> =
         Arc::new(tokio::sync::Mutex::new(HashMap::new()));
 
+    let wakeup = Arc::new(tokio::sync::Notify::new());
+
+    let channels = Arc::new(ChannelCache::new(
+        clock.clone(),
+        chrono::TimeDelta::hours(1),
+    ));
+
     let options = poise::FrameworkOptions {
         commands: vec![
             commands::setchannel(),
@@ -71,12 +174,16 @@ async fn main() {
             commands::updateuser(),
             commands::addschedule(),
             commands::addscheduleadmin(),
+            commands::remind(),
             commands::getscheduleall(),
             commands::getschedule(),
             commands::getscheduleadmin(),
             commands::deleteschedule(),
             commands::deletescheduleadmin(),
+            commands::restoreschedule(),
+            commands::restorescheduleadmin(),
             commands::shiftschedule(),
+            commands::done(),
         ],
         prefix_options: poise::PrefixFrameworkOptions {
             prefix: Some("~".into()),
@@ -94,13 +201,24 @@ async fn main() {
         // This code is run before every command
         pre_command: |ctx| {
             Box::pin(async move {
-                println!("Executing command {}...", ctx.command().qualified_name);
+                metrics::counter!(
+                    monitoring::COMMANDS,
+                    "command" => ctx.command().qualified_name.clone()
+                )
+                .increment(1);
+
+                tracing::info!(
+                    command = %ctx.command().qualified_name,
+                    guild = ctx.guild_id().map(|e| e.get()),
+                    user = ctx.author().id.get(),
+                    "executing command"
+                );
             })
         },
         // This code is run after a command if it was successful (returned Ok)
         post_command: |ctx| {
             Box::pin(async move {
-                println!("Executed command {}!", ctx.command().qualified_name);
+                tracing::debug!(command = %ctx.command().qualified_name, "executed command");
             })
         },
         // Every command invocation must pass this check to continue execution
@@ -123,17 +241,36 @@ async fn main() {
         ..Default::default()
     };
 
+    // Each running instance needs its own name so they can share the schedule
+    let instance = match var("INSTANCE_ID") {
+        Ok(e) => e,
+        Err(_) => format!("reminderpup-{}", std::process::id()),
+    };
+
     let db_clone = db.clone();
     let active_messages_clone = active_messages.clone();
+    let clock_clone = clock.clone();
+    let wakeup_clone = wakeup.clone();
+    let channels_clone = channels.clone();
+    let instance_clone = instance.clone();
 
     let framework = poise::Framework::builder()
         .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
-                println!("Logged in as {}", _ready.user.name);
+                tracing::info!(user = %_ready.user.name, "logged in");
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
                 Ok(Data {
                     db: db_clone,
                     active_messages: active_messages_clone,
+                    archive_retention,
+                    clock: clock_clone,
+                    messenger: Arc::new(SerenityMessenger::new(
+                        ctx.http.clone(),
+                        channels_clone.clone(),
+                    )),
+                    wakeup: wakeup_clone,
+                    channels: channels_clone,
+                    instance: instance_clone,
                 })
             })
         })
@@ -142,9 +279,16 @@ async fn main() {
 
     let token = var("DISCORD_TOKEN")
         .expect("Missing `DISCORD_TOKEN` env var, see README for more information.");
-    let intents =
+    let mut intents =
         serenity::GatewayIntents::non_privileged() | serenity::GatewayIntents::MESSAGE_CONTENT;
 
+    // Member events pause the reminders of people who leave, the intent has to be
+    // turned on for the bot in the developer portal so it's opt in
+    match var("PAUSE_ON_MEMBER_LEAVE").as_deref() {
+        Ok("true") | Ok("1") => intents |= serenity::GatewayIntents::GUILD_MEMBERS,
+        _ => (),
+    };
+
     let mut client = serenity::ClientBuilder::new(token, intents)
         .framework(framework)
         .await
@@ -155,7 +299,27 @@ async fn main() {
     let tailwag_emoji = var("DISCORD_TAILWAG")
         .expect("Missing `DISCORD_TAILWAG` env var, see README for more information.");
 
-    repo::schedule::Scheduler::start(db, http, tailwag_emoji, active_messages);
+    let messenger: Arc<dyn Messenger> = Arc::new(SerenityMessenger::new(http, channels));
+
+    repo::schedule::Scheduler::new(
+        db,
+        messenger,
+        clock,
+        active_messages,
+        tailwag_emoji,
+        instance,
+        wakeup,
+    )
+    .start(archive_retention);
 
-    client.start().await.unwrap();
+    match client.start().await {
+        Ok(_) => (),
+        Err(serenity::Error::Gateway(serenity::GatewayError::DisallowedGatewayIntents)) => {
+            match intents.contains(serenity::GatewayIntents::GUILD_MEMBERS) {
+                true => panic!("Discord won't let puppy use its intents, turn on the Message Content and Server Members intents for the bot in the developer portal or unset PAUSE_ON_MEMBER_LEAVE"),
+                false => panic!("Discord won't let puppy use its intents, turn on the Message Content intent for the bot in the developer portal"),
+            }
+        }
+        Err(e) => panic!("Puppy stopped: {:?}", e),
+    };
 }
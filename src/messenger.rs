@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use sqlx::types::chrono::{DateTime, Utc};
+
+use poise::serenity_prelude as serenity;
+
+use crate::clock::Clock;
+
+#[derive(Debug)]
+pub enum MessengerError {
+    /// The channel is no longer in the guild
+    ChannelNotFound,
+    Discord(serenity::Error),
+}
+
+impl std::fmt::Display for MessengerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessengerError::ChannelNotFound => write!(f, "channel not found"),
+            MessengerError::Discord(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl MessengerError {
+    /// Whether trying again can't help because the channel is gone or puppy
+    /// isn't allowed to post in it any more
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            MessengerError::ChannelNotFound => true,
+            MessengerError::Discord(serenity::Error::Http(
+                serenity::HttpError::UnsuccessfulRequest(e),
+            )) => matches!(
+                // Unknown channel, unknown guild, missing access and missing permissions
+                e.error.code,
+                10003 | 10004 | 50001 | 50013
+            ),
+            MessengerError::Discord(_) => false,
+        }
+    }
+
+    /// Short name for what went wrong, used to label failed deliveries
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MessengerError::ChannelNotFound => "channel_not_found",
+            MessengerError::Discord(serenity::Error::Http(
+                serenity::HttpError::UnsuccessfulRequest(e),
+            )) => match e.error.code {
+                10003 | 10004 => "unknown_channel",
+                50001 | 50013 => "missing_permissions",
+                _ if e.status_code.as_u16() == 429 => "rate_limited",
+                _ if e.status_code.is_server_error() => "discord_unavailable",
+                _ => "rejected",
+            },
+            MessengerError::Discord(serenity::Error::Http(_)) => "network",
+            MessengerError::Discord(_) => "other",
+        }
+    }
+}
+
+/// The few things the scheduler needs from Discord, so it can run against a fake in tests
+#[async_trait]
+pub trait Messenger: Send + Sync {
+    /// Posts a message in a guild channel and returns its id.
+    /// Discord drops a message with the same `nonce` as one sent in the last few
+    /// minutes, so a retried send does not post twice.
+    async fn send(
+        &self,
+        guild_id: i64,
+        channel_id: i64,
+        content: String,
+        nonce: Option<String>,
+    ) -> Result<u64, MessengerError>;
+
+    /// Replies to a message and returns the id of the reply
+    async fn reply(
+        &self,
+        channel_id: i64,
+        message_id: u64,
+        content: String,
+    ) -> Result<u64, MessengerError>;
+
+    /// Deletes a message puppy sent
+    async fn delete(&self, channel_id: i64, message_id: u64) -> Result<(), MessengerError>;
+
+    /// Sends a direct message to the owner of a guild, for problems only they can fix
+    async fn message_owner(&self, guild_id: i64, content: String) -> Result<u64, MessengerError>;
+}
+
+/// Which guild each recently seen channel belongs to, so sending a reminder
+/// doesn't need to fetch every channel in the guild first
+pub struct ChannelCache {
+    clock: Arc<dyn Clock>,
+    ttl: chrono::TimeDelta,
+    channels: std::sync::Mutex<HashMap<i64, (i64, DateTime<Utc>)>>,
+}
+
+impl ChannelCache {
+    pub fn new(clock: Arc<dyn Clock>, ttl: chrono::TimeDelta) -> ChannelCache {
+        ChannelCache {
+            clock,
+            ttl,
+            channels: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The guild a channel is in, if it was seen within the ttl
+    pub fn guild(&self, channel_id: i64) -> Option<i64> {
+        let now = self.clock.now();
+
+        match self.channels.lock().unwrap().get(&channel_id) {
+            Some((guild_id, seen)) if *seen + self.ttl > now => Some(*guild_id),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, channel_id: i64, guild_id: i64) {
+        self.channels
+            .lock()
+            .unwrap()
+            .insert(channel_id, (guild_id, self.clock.now()));
+    }
+
+    /// Forgets a channel so it is looked up again next time
+    pub fn remove(&self, channel_id: i64) {
+        self.channels.lock().unwrap().remove(&channel_id);
+    }
+}
+
+pub struct SerenityMessenger {
+    http: Arc<serenity::http::Http>,
+    channels: Arc<ChannelCache>,
+}
+
+impl SerenityMessenger {
+    pub fn new(http: Arc<serenity::http::Http>, channels: Arc<ChannelCache>) -> SerenityMessenger {
+        SerenityMessenger { http, channels }
+    }
+}
+
+#[async_trait]
+impl Messenger for SerenityMessenger {
+    async fn send(
+        &self,
+        guild_id: i64,
+        channel_id: i64,
+        content: String,
+        nonce: Option<String>,
+    ) -> Result<u64, MessengerError> {
+        if self.channels.guild(channel_id) != Some(guild_id) {
+            let channels = match serenity::GuildId::from(guild_id as u64)
+                .channels(&self.http)
+                .await
+            {
+                Ok(e) => e,
+                Err(e) => return Err(MessengerError::Discord(e)),
+            };
+
+            for id in channels.keys() {
+                self.channels.insert(id.get() as i64, guild_id);
+            }
+
+            if !channels.contains_key(&serenity::ChannelId::from(channel_id as u64)) {
+                return Err(MessengerError::ChannelNotFound);
+            }
+        }
+
+        let channel = serenity::ChannelId::from(channel_id as u64);
+
+        let mut message = serenity::CreateMessage::new().content(content);
+
+        if let Some(nonce) = nonce {
+            message = message
+                .nonce(serenity::Nonce::String(nonce))
+                .enforce_nonce(true);
+        }
+
+        match channel.send_message(&self.http, message).await {
+            Ok(e) => Ok(e.id.get()),
+            Err(e) => {
+                let e = MessengerError::Discord(e);
+
+                if e.is_permanent() {
+                    self.channels.remove(channel_id);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn reply(
+        &self,
+        channel_id: i64,
+        message_id: u64,
+        content: String,
+    ) -> Result<u64, MessengerError> {
+        let channel = serenity::ChannelId::from(channel_id as u64);
+
+        let message = serenity::CreateMessage::new()
+            .content(content)
+            .reference_message((channel, serenity::MessageId::from(message_id)));
+
+        match channel.send_message(&self.http, message).await {
+            Ok(e) => Ok(e.id.get()),
+            Err(e) => Err(MessengerError::Discord(e)),
+        }
+    }
+
+    async fn delete(&self, channel_id: i64, message_id: u64) -> Result<(), MessengerError> {
+        match serenity::ChannelId::from(channel_id as u64)
+            .delete_message(&self.http, serenity::MessageId::from(message_id))
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(MessengerError::Discord(e)),
+        }
+    }
+
+    async fn message_owner(&self, guild_id: i64, content: String) -> Result<u64, MessengerError> {
+        let guild = match serenity::GuildId::from(guild_id as u64)
+            .to_partial_guild(&self.http)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(MessengerError::Discord(e)),
+        };
+
+        let dm = match guild.owner_id.create_dm_channel(&self.http).await {
+            Ok(e) => e,
+            Err(e) => return Err(MessengerError::Discord(e)),
+        };
+
+        match dm.say(&self.http, content).await {
+            Ok(e) => Ok(e.id.get()),
+            Err(e) => Err(MessengerError::Discord(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub struct SentMessage {
+    pub id: u64,
+    pub channel_id: i64,
+    pub content: String,
+    pub reply_to: Option<u64>,
+    pub nonce: Option<String>,
+}
+
+/// Records everything sent instead of talking to Discord
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeMessenger {
+    sent: std::sync::Mutex<Vec<SentMessage>>,
+    failures: std::sync::Mutex<usize>,
+    removed_channels: std::sync::Mutex<Vec<i64>>,
+    owner_messages: std::sync::Mutex<Vec<(i64, String)>>,
+    deleted: std::sync::Mutex<Vec<u64>>,
+}
+
+#[cfg(test)]
+impl FakeMessenger {
+    pub fn new() -> FakeMessenger {
+        FakeMessenger::default()
+    }
+
+    pub fn sent(&self) -> Vec<SentMessage> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    pub fn last(&self) -> SentMessage {
+        self.sent().last().unwrap().clone()
+    }
+
+    /// Makes the next `count` sends fail as if Discord was down
+    pub fn fail_sends(&self, count: usize) {
+        *self.failures.lock().unwrap() = count;
+    }
+
+    /// Makes sends to a channel fail as if it had been deleted
+    pub fn remove_channel(&self, channel_id: i64) {
+        self.removed_channels.lock().unwrap().push(channel_id);
+    }
+
+    /// Ids of the messages puppy deleted
+    pub fn deleted(&self) -> Vec<u64> {
+        self.deleted.lock().unwrap().clone()
+    }
+
+    /// Direct messages sent to guild owners as (guild, content)
+    pub fn owner_messages(&self) -> Vec<(i64, String)> {
+        self.owner_messages.lock().unwrap().clone()
+    }
+
+    fn record(
+        &self,
+        channel_id: i64,
+        content: String,
+        reply_to: Option<u64>,
+        nonce: Option<String>,
+    ) -> u64 {
+        let mut sent = self.sent.lock().unwrap();
+
+        // Like Discord, a repeated nonce gives back the message already sent
+        if let Some(m) = sent.iter().find(|m| nonce.is_some() && m.nonce == nonce) {
+            return m.id;
+        }
+
+        let id = sent.len() as u64 + 1;
+
+        sent.push(SentMessage {
+            id,
+            channel_id,
+            content,
+            reply_to,
+            nonce,
+        });
+
+        id
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Messenger for FakeMessenger {
+    async fn send(
+        &self,
+        _guild_id: i64,
+        channel_id: i64,
+        content: String,
+        nonce: Option<String>,
+    ) -> Result<u64, MessengerError> {
+        if self.removed_channels.lock().unwrap().contains(&channel_id) {
+            return Err(MessengerError::ChannelNotFound);
+        }
+
+        let mut failures = self.failures.lock().unwrap();
+
+        if *failures > 0 {
+            *failures -= 1;
+            return Err(MessengerError::Discord(serenity::Error::Other(
+                "discord is down",
+            )));
+        }
+
+        drop(failures);
+
+        Ok(self.record(channel_id, content, None, nonce))
+    }
+
+    async fn reply(
+        &self,
+        channel_id: i64,
+        message_id: u64,
+        content: String,
+    ) -> Result<u64, MessengerError> {
+        Ok(self.record(channel_id, content, Some(message_id), None))
+    }
+
+    async fn delete(&self, _channel_id: i64, message_id: u64) -> Result<(), MessengerError> {
+        self.deleted.lock().unwrap().push(message_id);
+
+        Ok(())
+    }
+
+    async fn message_owner(&self, guild_id: i64, content: String) -> Result<u64, MessengerError> {
+        let mut owner_messages = self.owner_messages.lock().unwrap();
+        owner_messages.push((guild_id, content));
+
+        Ok(owner_messages.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn cached_channel_expires() {
+        let clock = Arc::new(ManualClock::new(
+            DateTime::parse_from_rfc3339("2025-01-01T08:00:00Z")
+                .unwrap()
+                .to_utc(),
+        ));
+        let cache = ChannelCache::new(clock.clone(), chrono::TimeDelta::minutes(10));
+
+        assert_eq!(cache.guild(200), None);
+
+        cache.insert(200, 100);
+        assert_eq!(cache.guild(200), Some(100));
+
+        clock.advance(chrono::TimeDelta::minutes(10));
+        assert_eq!(cache.guild(200), None);
+    }
+
+    #[test]
+    fn error_kind_names_the_failure() {
+        assert_eq!(MessengerError::ChannelNotFound.kind(), "channel_not_found");
+        assert_eq!(
+            MessengerError::Discord(serenity::Error::Other("gone")).kind(),
+            "other"
+        );
+    }
+
+    #[test]
+    fn removed_channel_is_forgotten() {
+        let cache = ChannelCache::new(
+            Arc::new(crate::clock::SystemClock),
+            chrono::TimeDelta::minutes(10),
+        );
+
+        cache.insert(200, 100);
+        cache.remove(200);
+
+        assert_eq!(cache.guild(200), None);
+    }
+}
@@ -0,0 +1,44 @@
+use std::net::SocketAddr;
+
+use metrics::{describe_counter, describe_gauge, Unit};
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder};
+
+pub const REMINDERS_SENT: &str = "reminderpup_reminders_sent_total";
+pub const NAGS_SENT: &str = "reminderpup_nags_sent_total";
+pub const ACKNOWLEDGEMENTS: &str = "reminderpup_acknowledgements_total";
+pub const MISSES: &str = "reminderpup_misses_total";
+/// Labelled with `kind`, see `MessengerError::kind`
+pub const DELIVERY_FAILURES: &str = "reminderpup_delivery_failures_total";
+/// Labelled with `command`
+pub const COMMANDS: &str = "reminderpup_commands_total";
+pub const DUE_UNSENT: &str = "reminderpup_due_unsent";
+pub const SCHEDULER_LAG: &str = "reminderpup_scheduler_lag_seconds";
+
+/// Serves the metrics for Prometheus to scrape at `addr`. Needs to be called
+/// from inside the tokio runtime, which runs the listener.
+pub fn install(addr: SocketAddr) -> Result<(), BuildError> {
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .install()?;
+
+    describe_counter!(REMINDERS_SENT, "Reminders puppy has sent");
+    describe_counter!(NAGS_SENT, "Nags sent about unanswered reminders");
+    describe_counter!(ACKNOWLEDGEMENTS, "Reminders someone said they had done");
+    describe_counter!(
+        MISSES,
+        "Reminders nobody answered before the next one went out"
+    );
+    describe_counter!(DELIVERY_FAILURES, "Failed attempts at sending a reminder");
+    describe_counter!(COMMANDS, "Commands run");
+    describe_gauge!(
+        DUE_UNSENT,
+        "Runs that are due but haven't been sent yet, as of the last scheduler pass"
+    );
+    describe_gauge!(
+        SCHEDULER_LAG,
+        Unit::Seconds,
+        "How long after it was due the last reminder was sent"
+    );
+
+    Ok(())
+}
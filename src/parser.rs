@@ -0,0 +1,888 @@
+use std::fmt;
+
+use chrono::{NaiveDate, NaiveTime, TimeDelta, Timelike};
+
+/// A task worked out from a natural language reminder
+#[derive(Clone, Debug)]
+pub struct ParsedTask {
+    pub task: String,
+    pub task_secondary: String,
+    pub interval: TimeDelta,
+    /// Local times of day the task fires at, empty starts one interval from now
+    pub times: Vec<NaiveTime>,
+    pub max_runs: Option<i32>,
+    /// How long from now the task should keep running for
+    pub duration: Option<TimeDelta>,
+    /// Last local date the task runs on
+    pub until: Option<NaiveDate>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ParseError {
+    MissingTask,
+    MissingSchedule(String),
+    Ambiguous {
+        reason: String,
+        suggestions: Vec<String>,
+    },
+    TooFrequent,
+    TooRare,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MissingTask => {
+                write!(f, "Puppy doesn't know what to remind you about.\nTry something like `take meds every day at 9am`")
+            }
+            ParseError::MissingSchedule(task) => {
+                write!(
+                    f,
+                    "Puppy doesn't know when to remind you.\nTry something like:"
+                )?;
+                for s in [
+                    format!("{} every day at 9am", task),
+                    format!("{} every 4 hours", task),
+                    format!("{} every day at 9am and 9pm", task),
+                ] {
+                    write!(f, "\n`{}`", s)?;
+                }
+                Ok(())
+            }
+            ParseError::Ambiguous {
+                reason,
+                suggestions,
+            } => {
+                write!(f, "Puppy is confused, {}.", reason)?;
+                if !suggestions.is_empty() {
+                    write!(f, "\nDid you mean:")?;
+                    for s in suggestions {
+                        write!(f, "\n`{}`", s)?;
+                    }
+                }
+                Ok(())
+            }
+            ParseError::TooFrequent => write!(
+                f,
+                "Puppy can only bark every 4 hours.\nPlease set the interval to atleast 4 hours."
+            ),
+            ParseError::TooRare => write!(
+                f,
+                "Puppy can't wait that long between barks.\nPlease set the interval to at most 120 days."
+            ),
+        }
+    }
+}
+
+/// Why a run of words could not be read as a schedule
+enum ScheduleError {
+    /// The words are not a schedule at all, most likely part of the task
+    Unrecognised,
+    Invalid(ParseError),
+}
+
+#[derive(Default)]
+struct Schedule {
+    interval: Option<TimeDelta>,
+    times: Vec<NaiveTime>,
+    max_runs: Option<i32>,
+    duration: Option<TimeDelta>,
+    until: Option<NaiveDate>,
+}
+
+/// Parses one or more reminders into tasks. Reminders are split on `;`, new
+/// lines, or an `and` between two reminders that each have their own schedule
+/// like `take meds every day at 9am and stretch every 4 hours`.
+pub fn parse_reminders(input: &str) -> Result<Vec<ParsedTask>, ParseError> {
+    let mut tasks = Vec::new();
+
+    for clause in input.split([';', '\n']).filter(|e| !e.trim().is_empty()) {
+        tasks.extend(split_reminders(clause)?);
+    }
+
+    match tasks.is_empty() {
+        true => Err(ParseError::MissingTask),
+        false => Ok(tasks),
+    }
+}
+
+/// Tries the last `and` first so times joined with `and` stay on one task,
+/// `9am and 9pm` on its own is never a reminder.
+fn split_reminders(input: &str) -> Result<Vec<ParsedTask>, ParseError> {
+    let words: Vec<&str> = input.split_whitespace().collect();
+
+    for i in (1..words.len()).rev() {
+        if !words[i].eq_ignore_ascii_case("and") {
+            continue;
+        }
+
+        let last = match parse_reminder(&words[i + 1..].join(" ")) {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+
+        if let Ok(mut tasks) = split_reminders(&words[..i].join(" ")) {
+            tasks.push(last);
+            return Ok(tasks);
+        }
+    }
+
+    parse_reminder(input).map(|e| vec![e])
+}
+
+/// Parses phrases like `take meds every day at 9am and 9pm` or
+/// `me to stretch every 4 hours` into a task.
+pub fn parse_reminder(input: &str) -> Result<ParsedTask, ParseError> {
+    let words: Vec<&str> = input
+        .split_whitespace()
+        .map(|w| w.trim_end_matches(['.', '!', '?']))
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    let mut start = 0;
+    while start < words.len() && ["me", "to"].contains(&words[start].to_lowercase().as_str()) {
+        start += 1;
+    }
+    let words = &words[start..];
+
+    let lower: Vec<String> = words
+        .iter()
+        .map(|w| w.to_lowercase().trim_end_matches(',').to_owned())
+        .collect();
+
+    let mut first_error: Option<ParseError> = None;
+
+    for split in 0..=lower.len() {
+        let schedule = match parse_schedule(&lower[split..]) {
+            Ok(e) => e,
+            Err(ScheduleError::Unrecognised) => continue,
+            Err(ScheduleError::Invalid(e)) => {
+                if first_error.is_none() {
+                    first_error = Some(e);
+                }
+                continue;
+            }
+        };
+
+        // Something earlier looked like a schedule but didn't make sense,
+        // don't quietly swallow it into the task text
+        if first_error.is_some() {
+            break;
+        }
+
+        let task = task_text(&words[..split]);
+
+        if task.is_empty() {
+            return Err(ParseError::MissingTask);
+        }
+
+        return build_task(&task, schedule);
+    }
+
+    let task = task_text(words);
+
+    match first_error {
+        Some(ParseError::Ambiguous {
+            reason,
+            suggestions,
+        }) => Err(ParseError::Ambiguous {
+            reason,
+            suggestions: suggestions
+                .iter()
+                .map(|s| format!("{} {}", task_guess(&lower, words), s))
+                .collect(),
+        }),
+        Some(e) => Err(e),
+        None if task.is_empty() => Err(ParseError::MissingTask),
+        None => Err(ParseError::MissingSchedule(task)),
+    }
+}
+
+/// Builds a best guess at the task part of an input that failed to parse,
+/// everything before the first word that looks like the start of a schedule.
+fn task_guess(lower: &[String], words: &[&str]) -> String {
+    let end = lower
+        .iter()
+        .position(|w| {
+            [
+                "every", "each", "daily", "weekly", "hourly", "at", "twice", "thrice",
+            ]
+            .contains(&w.as_str())
+        })
+        .unwrap_or(words.len());
+
+    task_text(&words[..end])
+}
+
+fn task_text(words: &[&str]) -> String {
+    words
+        .iter()
+        .map(|w| match w.to_lowercase().as_str() {
+            "my" => "your",
+            "myself" => "yourself",
+            _ => w,
+        })
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .trim_end_matches(',')
+        .to_owned()
+}
+
+fn build_task(task: &str, schedule: Schedule) -> Result<ParsedTask, ParseError> {
+    let interval = match schedule.interval {
+        Some(e) => e,
+        None => {
+            return Err(ParseError::Ambiguous {
+                reason: "puppy can only do repeating reminders".to_owned(),
+                suggestions: schedule
+                    .times
+                    .iter()
+                    .map(|t| format!("{} every day at {}", task, format_time(t)))
+                    .collect(),
+            })
+        }
+    };
+
+    if interval < TimeDelta::hours(4) {
+        return Err(ParseError::TooFrequent);
+    }
+
+    // Same cap as the days option of /addschedule
+    if interval > TimeDelta::days(120) {
+        return Err(ParseError::TooRare);
+    }
+
+    let mut times = schedule.times.clone();
+    times.sort();
+    times.dedup();
+
+    if times.len() > 1 && (interval < TimeDelta::days(1) || interval.num_seconds() % 86400 != 0) {
+        return Err(ParseError::Ambiguous {
+            reason: "puppy can only use more than one time with whole days".to_owned(),
+            suggestions: vec![
+                format!(
+                    "{} every {} starting at {}",
+                    task,
+                    crate::util::timedelta_to_string(&interval),
+                    format_time(&times[0])
+                ),
+                format!(
+                    "{} every day at {}",
+                    task,
+                    times
+                        .iter()
+                        .map(format_time)
+                        .collect::<Vec<String>>()
+                        .join(" and ")
+                ),
+            ],
+        });
+    }
+
+    Ok(ParsedTask {
+        task: task.to_owned(),
+        task_secondary: past_tense(task),
+        interval,
+        times,
+        max_runs: schedule.max_runs,
+        duration: schedule.duration,
+        until: schedule.until,
+    })
+}
+
+fn parse_schedule(words: &[String]) -> Result<Schedule, ScheduleError> {
+    if words.is_empty() {
+        return Err(ScheduleError::Unrecognised);
+    }
+
+    let mut schedule = Schedule::default();
+    let mut i = 0;
+
+    while i < words.len() {
+        let word = words[i].as_str();
+
+        match word {
+            "and" | "then" => i += 1,
+            "every" | "each" => {
+                if schedule.interval.is_some() {
+                    return Err(ScheduleError::Unrecognised);
+                }
+                i += 1;
+                schedule.interval = Some(parse_every(words, &mut i)?);
+            }
+            "daily" => {
+                schedule.interval = set_once(schedule.interval, TimeDelta::days(1))?;
+                i += 1;
+            }
+            "weekly" => {
+                schedule.interval = set_once(schedule.interval, TimeDelta::weeks(1))?;
+                i += 1;
+            }
+            "hourly" => {
+                schedule.interval = set_once(schedule.interval, TimeDelta::hours(1))?;
+                i += 1;
+            }
+            "twice" | "thrice" | "once" => {
+                if !matches!(
+                    words.get(i + 1).map(|w| w.as_str()),
+                    Some("a") | Some("per") | Some("daily")
+                ) {
+                    return Err(ScheduleError::Unrecognised);
+                }
+                let mut suggestions = vec!["every day at 9am".to_owned()];
+                if word != "once" {
+                    suggestions.push("every day at 9am and 9pm".to_owned());
+                    suggestions.push("every 12 hours starting at 8am".to_owned());
+                }
+                return Err(ScheduleError::Invalid(ParseError::Ambiguous {
+                    reason: format!("puppy doesn't know which times \"{}\" should be", word),
+                    suggestions,
+                }));
+            }
+            "for" => {
+                i += 1;
+                parse_for(words, &mut i, &mut schedule)?;
+            }
+            "until" | "till" => {
+                i += 1;
+                let date = match words
+                    .get(i)
+                    .map(|w| NaiveDate::parse_from_str(w, "%Y-%m-%d"))
+                {
+                    Some(Ok(e)) => e,
+                    _ => return Err(ScheduleError::Unrecognised),
+                };
+                if schedule.until.is_some() || schedule.duration.is_some() {
+                    return Err(ScheduleError::Unrecognised);
+                }
+                schedule.until = Some(date);
+                i += 1;
+            }
+            "at" | "from" | "starting" => {
+                i += 1;
+                if word == "starting"
+                    && matches!(words.get(i).map(|w| w.as_str()), Some("at") | Some("from"))
+                {
+                    i += 1;
+                }
+                schedule.times.push(parse_time(words, &mut i)?);
+
+                // Allow lists like `at 9am, 1pm and 9pm`
+                while i < words.len() {
+                    let mut j = i;
+                    if words[j] == "and" {
+                        j += 1;
+                    }
+                    if words.get(j).map(|w| w.as_str()) == Some("at") {
+                        j += 1;
+                    }
+                    match parse_time(words, &mut j) {
+                        Ok(t) => {
+                            schedule.times.push(t);
+                            i = j;
+                        }
+                        Err(ScheduleError::Unrecognised) => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            _ => return Err(ScheduleError::Unrecognised),
+        }
+    }
+
+    if schedule.interval.is_none() && schedule.times.is_empty() {
+        return Err(ScheduleError::Unrecognised);
+    }
+
+    Ok(schedule)
+}
+
+/// Parses what follows `for`, e.g. `7 days`, `a week` or `10 times`
+fn parse_for(
+    words: &[String],
+    i: &mut usize,
+    schedule: &mut Schedule,
+) -> Result<(), ScheduleError> {
+    let count: i64 = match words.get(*i) {
+        Some(w) => match w.parse() {
+            Ok(e) => e,
+            Err(_) => match number_word(w) {
+                Some(e) => e,
+                None => return Err(ScheduleError::Unrecognised),
+            },
+        },
+        None => return Err(ScheduleError::Unrecognised),
+    };
+
+    if count <= 0 || count > 1000 {
+        return Err(ScheduleError::Unrecognised);
+    }
+
+    let unit = match words.get(*i + 1) {
+        Some(e) => e.as_str(),
+        None => return Err(ScheduleError::Unrecognised),
+    };
+
+    match unit {
+        "time" | "times" | "reminders" | "doses" => {
+            if schedule.max_runs.is_some() {
+                return Err(ScheduleError::Unrecognised);
+            }
+            schedule.max_runs = Some(count as i32);
+        }
+        "month" | "months" => {
+            if schedule.duration.is_some() || schedule.until.is_some() {
+                return Err(ScheduleError::Unrecognised);
+            }
+            schedule.duration = Some(TimeDelta::days(30) * count as i32);
+        }
+        _ => match parse_unit(unit) {
+            Some(d) if d >= TimeDelta::days(1) => {
+                if schedule.duration.is_some() || schedule.until.is_some() {
+                    return Err(ScheduleError::Unrecognised);
+                }
+                schedule.duration = Some(d * count as i32);
+            }
+            _ => return Err(ScheduleError::Unrecognised),
+        },
+    }
+
+    *i += 2;
+
+    Ok(())
+}
+
+fn set_once(
+    current: Option<TimeDelta>,
+    value: TimeDelta,
+) -> Result<Option<TimeDelta>, ScheduleError> {
+    match current {
+        Some(_) => Err(ScheduleError::Unrecognised),
+        None => Ok(Some(value)),
+    }
+}
+
+/// Parses what follows `every`, e.g. `day`, `other day`, `4 hours`
+fn parse_every(words: &[String], i: &mut usize) -> Result<TimeDelta, ScheduleError> {
+    let word = match words.get(*i) {
+        Some(e) => e.as_str(),
+        None => return Err(ScheduleError::Unrecognised),
+    };
+
+    if let Some(unit) = parse_unit(word) {
+        *i += 1;
+        return Ok(unit);
+    }
+
+    match word {
+        "other" | "second" => {
+            *i += 1;
+            match words.get(*i).and_then(|w| parse_unit(w)) {
+                Some(unit) => {
+                    *i += 1;
+                    Ok(unit * 2)
+                }
+                None => Err(ScheduleError::Unrecognised),
+            }
+        }
+        "morning" | "afternoon" | "evening" | "night" => {
+            let example = match word {
+                "morning" => "9am",
+                "afternoon" => "2pm",
+                "evening" => "6pm",
+                _ => "9pm",
+            };
+            Err(ScheduleError::Invalid(ParseError::Ambiguous {
+                reason: format!("puppy doesn't know what time \"every {}\" is", word),
+                suggestions: vec![format!("every day at {}", example)],
+            }))
+        }
+        _ => {
+            let count: i64 = match word.parse() {
+                Ok(e) => e,
+                Err(_) => match number_word(word) {
+                    Some(e) => e,
+                    None => return Err(ScheduleError::Unrecognised),
+                },
+            };
+            *i += 1;
+
+            match words.get(*i).and_then(|w| parse_unit(w)) {
+                Some(unit) if count > 0 && count <= 1000 => {
+                    *i += 1;
+                    Ok(unit * count as i32)
+                }
+                Some(_) => Err(ScheduleError::Unrecognised),
+                None => Err(ScheduleError::Invalid(ParseError::Ambiguous {
+                    reason: format!("puppy doesn't know what \"every {}\" is counting", word),
+                    suggestions: vec![
+                        format!("every {} hours", count),
+                        format!("every {} days", count),
+                    ],
+                })),
+            }
+        }
+    }
+}
+
+fn parse_unit(word: &str) -> Option<TimeDelta> {
+    match word {
+        "minute" | "minutes" | "min" | "mins" => Some(TimeDelta::minutes(1)),
+        "hour" | "hours" | "hr" | "hrs" | "h" => Some(TimeDelta::hours(1)),
+        "day" | "days" => Some(TimeDelta::days(1)),
+        "week" | "weeks" => Some(TimeDelta::weeks(1)),
+        _ => None,
+    }
+}
+
+fn number_word(word: &str) -> Option<i64> {
+    let n = match word {
+        "one" | "a" | "an" => 1,
+        "two" => 2,
+        "three" => 3,
+        "four" => 4,
+        "five" => 5,
+        "six" => 6,
+        "seven" => 7,
+        "eight" => 8,
+        "nine" => 9,
+        "ten" => 10,
+        "eleven" => 11,
+        "twelve" => 12,
+        _ => return None,
+    };
+    Some(n)
+}
+
+/// Parses a time of day such as `9am`, `9 pm`, `21:00`, `9:30pm` or `noon`
+fn parse_time(words: &[String], i: &mut usize) -> Result<NaiveTime, ScheduleError> {
+    let word = match words.get(*i) {
+        Some(e) => e.as_str(),
+        None => return Err(ScheduleError::Unrecognised),
+    };
+
+    match word {
+        "noon" | "midday" => {
+            *i += 1;
+            return Ok(NaiveTime::from_hms_opt(12, 0, 0).unwrap());
+        }
+        "midnight" => {
+            *i += 1;
+            return Ok(NaiveTime::from_hms_opt(0, 0, 0).unwrap());
+        }
+        _ => (),
+    }
+
+    let (clock, mut meridiem) = match word.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(idx) => (&word[..idx], Some(&word[idx..])),
+        None => (word, None),
+    };
+
+    let (hour, minute, has_minutes) = match clock.split_once(':') {
+        Some((h, m)) => match (h.parse::<u32>(), m.parse::<u32>()) {
+            (Ok(h), Ok(m)) if m < 60 => (h, m, true),
+            _ => return Err(ScheduleError::Unrecognised),
+        },
+        None => match clock.parse::<u32>() {
+            Ok(h) => (h, 0, false),
+            Err(_) => return Err(ScheduleError::Unrecognised),
+        },
+    };
+
+    let mut consumed = 1;
+    if meridiem.is_none() {
+        if let Some(next) = words.get(*i + 1) {
+            if is_meridiem(next) {
+                meridiem = Some(next.as_str());
+                consumed = 2;
+            }
+        }
+    }
+
+    let hour = match meridiem {
+        Some(m) if is_meridiem(m) => {
+            if hour == 0 || hour > 12 {
+                return Err(ScheduleError::Unrecognised);
+            }
+            let pm = m.starts_with('p');
+            match (hour, pm) {
+                (12, false) => 0,
+                (12, true) => 12,
+                (h, false) => h,
+                (h, true) => h + 12,
+            }
+        }
+        Some(_) => return Err(ScheduleError::Unrecognised),
+        None => {
+            if hour > 23 {
+                return Err(ScheduleError::Unrecognised);
+            }
+            if !has_minutes && (1..=12).contains(&hour) {
+                return Err(ScheduleError::Invalid(ParseError::Ambiguous {
+                    reason: format!(
+                        "puppy doesn't know if {} is in the morning or evening",
+                        hour
+                    ),
+                    suggestions: vec![
+                        format!("every day at {}am", hour),
+                        format!("every day at {}pm", hour),
+                    ],
+                }));
+            }
+            hour
+        }
+    };
+
+    *i += consumed;
+
+    match NaiveTime::from_hms_opt(hour, minute, 0) {
+        Some(e) => Ok(e),
+        None => Err(ScheduleError::Unrecognised),
+    }
+}
+
+fn is_meridiem(word: &str) -> bool {
+    ["am", "pm", "a.m", "p.m", "a.m.", "p.m."].contains(&word)
+}
+
+fn format_time(time: &NaiveTime) -> String {
+    match time.minute() {
+        0 => time.format("%-I%P").to_string(),
+        _ => time.format("%-I:%M%P").to_string(),
+    }
+}
+
+/// Best effort past tense of a task so praise reads naturally,
+/// `take your meds` becomes `taken your meds`
+pub fn past_tense(task: &str) -> String {
+    let (verb, rest) = match task.split_once(' ') {
+        Some((v, r)) => (v, Some(r)),
+        None => (task, None),
+    };
+
+    let lower = verb.to_lowercase();
+
+    let irregular = match lower.as_str() {
+        "take" => Some("taken"),
+        "drink" => Some("drunk"),
+        "eat" => Some("eaten"),
+        "go" => Some("gone"),
+        "do" => Some("done"),
+        "make" => Some("made"),
+        "have" => Some("had"),
+        "get" => Some("got"),
+        "write" => Some("written"),
+        "read" => Some("read"),
+        "give" => Some("given"),
+        "feed" => Some("fed"),
+        "put" => Some("put"),
+        "run" => Some("run"),
+        "sleep" => Some("slept"),
+        "buy" => Some("bought"),
+        "bring" => Some("brought"),
+        "pay" => Some("paid"),
+        "send" => Some("sent"),
+        "set" => Some("set"),
+        "sit" => Some("sat"),
+        "stand" => Some("stood"),
+        "swim" => Some("swum"),
+        "ride" => Some("ridden"),
+        "shower" => Some("showered"),
+        _ => None,
+    };
+
+    let past = match irregular {
+        Some(e) => e.to_owned(),
+        None if lower.ends_with('e') => format!("{}d", lower),
+        None if lower.ends_with('y')
+            && !lower.ends_with("ay")
+            && !lower.ends_with("ey")
+            && !lower.ends_with("oy") =>
+        {
+            format!("{}ied", &lower[..lower.len() - 1])
+        }
+        None => format!("{}ed", lower),
+    };
+
+    match rest {
+        Some(r) => format!("{} {}", past, r),
+        None => past,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn suggestions(error: ParseError) -> Vec<String> {
+        match error {
+            ParseError::Ambiguous { suggestions, .. } => suggestions,
+            e => panic!("expected an ambiguous error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn daily_times_are_parsed() {
+        let task = parse_reminder("take my meds every day at 9am and 9pm").unwrap();
+
+        assert_eq!(task.task, "take your meds");
+        assert_eq!(task.task_secondary, "taken your meds");
+        assert_eq!(task.interval, TimeDelta::days(1));
+        assert_eq!(task.times, vec![time(9, 0), time(21, 0)]);
+        assert_eq!(task.max_runs, None);
+        assert_eq!(task.duration, None);
+        assert_eq!(task.until, None);
+    }
+
+    #[test]
+    fn leading_me_to_is_skipped() {
+        // `/remind me to ...` hands over everything after the command name
+        let task = parse_reminder("me to take my meds every day at 9am and 9pm").unwrap();
+
+        assert_eq!(task.task, "take your meds");
+        assert_eq!(task.times, vec![time(9, 0), time(21, 0)]);
+    }
+
+    #[test]
+    fn hourly_interval_has_no_times() {
+        let task = parse_reminder("stretch every 4 hours").unwrap();
+
+        assert_eq!(task.task, "stretch");
+        assert_eq!(task.interval, TimeDelta::hours(4));
+        assert!(task.times.is_empty());
+    }
+
+    #[test]
+    fn every_other_doubles_the_unit() {
+        let task = parse_reminder("water the plants every other week").unwrap();
+
+        assert_eq!(task.task, "water the plants");
+        assert_eq!(task.interval, TimeDelta::weeks(2));
+    }
+
+    #[test]
+    fn dotted_meridiem_is_understood() {
+        let task = parse_reminder("brush teeth every day at 9:30 p.m.").unwrap();
+
+        assert_eq!(task.times, vec![time(21, 30)]);
+    }
+
+    #[test]
+    fn bare_every_number_suggests_units() {
+        let error = parse_reminder("stretch every 9").unwrap_err();
+
+        assert_eq!(
+            suggestions(error),
+            vec!["stretch every 9 hours", "stretch every 9 days"]
+        );
+    }
+
+    #[test]
+    fn hour_without_meridiem_suggests_both() {
+        let error = parse_reminder("stretch every day at 9").unwrap_err();
+
+        assert_eq!(
+            suggestions(error),
+            vec!["stretch every day at 9am", "stretch every day at 9pm"]
+        );
+    }
+
+    #[test]
+    fn twice_a_day_suggests_times() {
+        let error = parse_reminder("take meds twice a day").unwrap_err();
+
+        assert_eq!(
+            suggestions(error),
+            vec![
+                "take meds every day at 9am",
+                "take meds every day at 9am and 9pm",
+                "take meds every 12 hours starting at 8am",
+            ]
+        );
+    }
+
+    #[test]
+    fn short_interval_is_too_frequent() {
+        assert!(matches!(
+            parse_reminder("stretch every 2 hours"),
+            Err(ParseError::TooFrequent)
+        ));
+    }
+
+    #[test]
+    fn long_interval_is_too_rare() {
+        assert!(matches!(
+            parse_reminder("check the smoke alarm every 20 weeks"),
+            Err(ParseError::TooRare)
+        ));
+    }
+
+    #[test]
+    fn separate_reminders_make_separate_tasks() {
+        let tasks =
+            parse_reminders("take my meds every day at 9am and 9pm and stretch every 4 hours")
+                .unwrap();
+
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].task, "take your meds");
+        assert_eq!(tasks[0].times, vec![time(9, 0), time(21, 0)]);
+        assert_eq!(tasks[1].task, "stretch");
+        assert_eq!(tasks[1].interval, TimeDelta::hours(4));
+
+        let tasks =
+            parse_reminders("feed the cat and dog every day at 8am; water the plants every week")
+                .unwrap();
+
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].task, "feed the cat and dog");
+        assert_eq!(tasks[1].task, "water the plants");
+    }
+
+    #[test]
+    fn missing_schedule_and_task_are_reported() {
+        assert!(matches!(
+            parse_reminder("stretch"),
+            Err(ParseError::MissingSchedule(task)) if task == "stretch"
+        ));
+        assert!(matches!(
+            parse_reminder("every day at 9am"),
+            Err(ParseError::MissingTask)
+        ));
+    }
+
+    #[test]
+    fn for_sets_how_long_to_run() {
+        let days = parse_reminder("take meds every day at 9am for 7 days").unwrap();
+        assert_eq!(days.duration, Some(TimeDelta::days(7)));
+
+        let weeks = parse_reminder("take meds every day at 9am for two weeks").unwrap();
+        assert_eq!(weeks.duration, Some(TimeDelta::weeks(2)));
+
+        let times = parse_reminder("take meds every day at 9am for 10 times").unwrap();
+        assert_eq!(times.max_runs, Some(10));
+        assert_eq!(times.duration, None);
+    }
+
+    #[test]
+    fn until_sets_the_last_day() {
+        let task = parse_reminder("take meds every day at 9am until 2025-03-01").unwrap();
+
+        assert_eq!(task.until, NaiveDate::from_ymd_opt(2025, 3, 1));
+        assert_eq!(task.duration, None);
+    }
+
+    #[test]
+    fn past_tense_rewrites_the_verb() {
+        assert_eq!(past_tense("take your meds"), "taken your meds");
+        assert_eq!(past_tense("Feed the cat"), "fed the cat");
+        assert_eq!(past_tense("water the plants"), "watered the plants");
+        assert_eq!(past_tense("tidy your room"), "tidied your room");
+        assert_eq!(past_tense("play piano"), "played piano");
+        assert_eq!(past_tense("stretch"), "stretched");
+        assert_eq!(past_tense("exercise"), "exercised");
+    }
+}
@@ -1,87 +1,14 @@
-use sqlx::migrate::MigrateError;
-use sqlx::migrate::Migrator;
+use chrono::TimeDelta;
 use sqlx::postgres::types::PgInterval;
 use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::PgPool;
 use sqlx::Pool;
 use sqlx::Postgres;
-use std::collections::HashMap;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
-use tokio::time::{sleep, Duration};
-
-#[derive(Clone, Debug)]
-pub struct Guild {
-    pub id: i64,
-    pub channel: i64,
-}
 
-#[derive(Clone, Debug)]
-pub struct Schedule {
-    pub id: i64,
-    pub guild_id: i64,
-    pub user_id: i64,
-    pub channel_id: i64,
-    pub task: String,
-    pub task_secondary: String,
-    pub praise: String,
-    pub praise_name: String,
-    pub interval: PgInterval,
-    pub created: DateTime<Utc>,
-    pub next_run: DateTime<Utc>,
-}
+use async_trait::async_trait;
 
-#[derive(Clone, Debug)]
-pub struct Task {
-    pub id: i64,
-    pub guild_id: i64,
-    pub user_id: i64,
-    pub task: String,
-    pub task_secondary: String,
-    pub interval: PgInterval,
-    pub created: DateTime<Utc>,
-    pub next_run: DateTime<Utc>,
-}
-
-#[derive(Clone, Debug)]
-pub struct UserTask {
-    pub id: i64,
-    pub guild_id: i64,
-    pub user_id: i64,
-    pub task: String,
-    pub task_secondary: String,
-    pub interval: PgInterval,
-    pub created: DateTime<Utc>,
-    pub next_run: DateTime<Utc>,
-    pub timezone: PgInterval,
-}
-
-#[derive(Clone, Debug)]
-pub struct User {
-    pub id: i64,
-    pub guild_id: i64,
-    pub user_id: i64,
-    pub praise: String,
-    pub praise_name: String,
-    pub timezone: PgInterval,
-}
-
-#[derive(Clone, Debug)]
-pub struct Timezone {
-    timezone: i16,
-}
+use crate::repo::storage::*;
 
-#[derive(Debug)]
-pub enum DatabaseErrors {
-    Error,
-    CannotConect,
-    MigrationFolderDoesNotExist,
-    MigrationError(MigrateError),
-    DoesNotExist,
-    GuildDoesNotExist,
-    UserDoesNotExist,
-    UserAlreadyExists,
-}
 #[derive(Clone)]
 pub struct Database {
     db: Pool<Postgres>,
@@ -89,7 +16,6 @@ pub struct Database {
 
 impl Database {
     pub async fn new(database_url: String) -> Result<Database, DatabaseErrors> {
-
         let sql_pool = match PgPool::connect(&database_url).await {
             Ok(e) => e,
             Err(_) => return Err(DatabaseErrors::CannotConect),
@@ -103,26 +29,37 @@ impl Database {
         };
     }
 
-    pub async fn get_guild(&self, guild: &i64) -> Result<Option<Guild>, DatabaseErrors> {
+    pub async fn update_task(
+        &self,
+        id: &i64,
+        schedule: Schedule,
+    ) -> Result<Schedule, DatabaseErrors> {
+        todo!()
+    }
+}
+
+#[async_trait]
+impl Storage for Database {
+    async fn get_guild(&self, guild: &i64) -> Result<Option<Guild>, DatabaseErrors> {
         let opt = match sqlx::query!("SELECT * FROM guilds WHERE guildID = $1", guild)
             .fetch_optional(&self.db)
             .await
         {
             Ok(e) => e,
-            Err(_) => return Err(DatabaseErrors::Error),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
         };
 
         match opt {
             Some(e) => {
                 return Ok(Some(Guild {
-                    id: e.guildid.clone(),
-                    channel: e.channel.clone(),
+                    id: e.guildid,
+                    channel: e.channel,
                 }));
             }
             None => return Ok(None),
         };
     }
-    pub async fn update_guild(&self, guild: &Guild) -> Result<Guild, DatabaseErrors> {
+    async fn update_guild(&self, guild: &Guild) -> Result<Guild, DatabaseErrors> {
         let opt = match self.get_guild(&guild.id).await {
             Ok(e) => e,
             Err(e) => return Err(e),
@@ -141,7 +78,7 @@ impl Database {
                     Ok(_) => {
                         return Ok(guild.clone());
                     }
-                    Err(_) => return Err(DatabaseErrors::Error),
+                    Err(e) => return Err(DatabaseErrors::Query(e)),
                 };
             }
             None => {
@@ -156,30 +93,52 @@ impl Database {
                     Ok(_) => {
                         return Ok(guild.clone());
                     }
-                    Err(_) => return Err(DatabaseErrors::Error),
+                    Err(e) => return Err(DatabaseErrors::Query(e)),
                 };
             }
         }
     }
 
-    pub async fn get_task_id(&self, id: &i64) -> Result<Option<Task>, DatabaseErrors> {
-        let opt = match sqlx::query!("SELECT s.id, s.guildid, s.userid, s.task, s.tasksecondary, s.interval, s.created, s.nextrun FROM public.schedule s INNER JOIN users u on s.userid = u.id AND s.id = $1", id)
+    async fn set_guild_removed(
+        &self,
+        guild_id: &i64,
+        removed: Option<&DateTime<Utc>>,
+    ) -> Result<(), DatabaseErrors> {
+        match sqlx::query!(
+            "UPDATE guilds SET removed = $2 WHERE guildID = $1",
+            guild_id,
+            removed.map(|e| e.naive_utc())
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn get_task_id(&self, id: &i64) -> Result<Option<Task>, DatabaseErrors> {
+        let opt = match sqlx::query!("SELECT s.id, s.guildid, s.userid, s.task, s.tasksecondary, s.interval, s.times, s.created, s.nextrun, s.enddate, s.maxruns, s.runs FROM public.schedule s INNER JOIN users u on s.userid = u.id AND s.id = $1 AND s.archived IS NULL", id)
             .fetch_optional(&self.db)
             .await
         {
             Ok(e) => e,
-            Err(_) => return Err(DatabaseErrors::Error),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
         };
 
         match opt {
             Some(e) => {
                 return Ok(Some(Task {
-                    id: e.id.clone(),
-                    guild_id: e.guildid.clone(),
-                    user_id: e.userid.clone(),
-                    interval: e.interval.clone(),
+                    id: e.id,
+                    guild_id: e.guildid,
+                    user_id: e.userid,
+                    interval: e.interval,
+                    times: e.times.clone(),
                     next_run: e.nextrun.clone().and_utc(),
                     created: e.created.clone().and_utc(),
+                    end_date: e.enddate.map(|d| d.and_utc()),
+                    max_runs: e.maxruns,
+                    runs: e.runs,
                     task: e.task.clone(),
                     task_secondary: e.tasksecondary.clone(),
                 }));
@@ -188,13 +147,13 @@ impl Database {
         };
     }
 
-    pub async fn get_task_user(
+    async fn get_task_user(
         &self,
         guild_id: &i64,
         user_id: &i64,
     ) -> Result<Vec<UserTask>, DatabaseErrors> {
         let tasks = match sqlx::query!(
-            "SELECT s.id, u.guildid, u.userid, s.task, s.tasksecondary, s.interval, s.created, s.nextrun, u.timezone FROM schedule s INNER JOIN users u  on s.userid = u.id AND u.guildid = $1 and u.userid = $2",
+            "SELECT s.id, u.guildid, u.userid, s.task, s.tasksecondary, s.interval, s.times, s.created, s.nextrun, s.enddate, s.maxruns, s.runs, u.timezone FROM schedule s INNER JOIN users u  on s.userid = u.id AND u.guildid = $1 and u.userid = $2 WHERE s.archived IS NULL",
             guild_id,
             user_id
         )
@@ -202,257 +161,582 @@ impl Database {
         .await
         {
             Ok(e) => e,
-            Err(_) => return Err(DatabaseErrors::Error),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
         };
 
         return Ok(tasks
             .iter()
             .map(|e| UserTask {
-                id: e.id.clone(),
-                guild_id: e.guildid.clone(),
-                user_id: e.userid.clone(),
-                interval: e
-                    .interval
-                    .clone(),
-                next_run: e
-                    .nextrun.and_utc()
-                    .clone(),
-                created: e
-                    .created
-                    .and_utc(),
+                id: e.id,
+                guild_id: e.guildid,
+                user_id: e.userid,
+                interval: e.interval,
+                times: e.times.clone(),
+                next_run: e.nextrun.and_utc(),
+                created: e.created.and_utc(),
+                end_date: e.enddate.map(|d| d.and_utc()),
+                max_runs: e.maxruns,
+                runs: e.runs,
                 task: e.task.clone(),
-                task_secondary: e
-                    .tasksecondary
-                    .clone(),
-                timezone: e.timezone.clone()
+                task_secondary: e.tasksecondary.clone(),
+                timezone: e.timezone,
             })
             .collect());
     }
 
-    pub async fn get_task_nextrun(
-        &self,
-        datetime: Option<DateTime<Utc>>,
-    ) -> Result<Vec<Schedule>, DatabaseErrors> {
-        let datetime: DateTime<Utc> = match datetime {
-            Some(e) => e,
-            None => Utc::now(),
+    async fn add_task(&self, schedule: Task) -> Result<Task, DatabaseErrors> {
+        let user = match self.get_user_id(&schedule.user_id).await {
+            Ok(e) => e,
+            Err(e) => return Err(e),
         };
 
-        let tasks = match sqlx::query!(
-            "SELECT s.id, s.guildid, g.channel, u.userid, s.task, s.tasksecondary, u.praise, u.praisename, s.interval, s.created, s.nextrun FROM public.schedule s INNER JOIN users u on s.userid = u.id AND s.nextrun < $1 INNER JOIN guilds g on s.guildid = g.guildid",
-            datetime.naive_utc()
-        )
-        .fetch_all(&self.db)
-        .await
+        match user {
+            Some(_) => {
+                match sqlx::query!("INSERT INTO schedule(guildid, userid, task, tasksecondary, interval, times, nextrun, enddate, maxruns) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id", schedule.guild_id, schedule.user_id, schedule.task, schedule.task_secondary, schedule.interval, &schedule.times, schedule.next_run.naive_utc(), schedule.end_date.map(|d| d.naive_utc()), schedule.max_runs).fetch_one(&self.db).await{
+                    Ok(e) => {
+                        return Ok(Task{
+                            id: e.id,
+                            .. schedule
+                        })
+                    }, 
+                    Err(e) => return Err(DatabaseErrors::Query(e)),
+
+                };
+            }
+            None => return Err(DatabaseErrors::UserDoesNotExist),
+        }
+    }
+
+    async fn delete_task(&self, id: &i64) -> Result<(), DatabaseErrors> {
+        match sqlx::query!("UPDATE schedule SET archived = (NOW() at time zone 'utc'), archivereason = 'deleted' WHERE id = $1 AND archived IS NULL", id).execute(&self.db).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e))
+        }
+    }
+
+    async fn delete_task_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors> {
+        match sqlx::query!("UPDATE schedule SET archived = (NOW() at time zone 'utc'), archivereason = 'deleted' WHERE guildid = $1 AND userid = $2 AND archived IS NULL", guild_id, user_id).execute(&self.db).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e))
+        }
+    }
+
+    async fn get_archived_task_id(&self, id: &i64) -> Result<Option<ArchivedTask>, DatabaseErrors> {
+        let opt = match sqlx::query!("SELECT s.id, s.guildid, u.userid, s.task, s.enddate, s.archived, s.archivereason, u.timezone FROM schedule s INNER JOIN users u on s.userid = u.id AND s.id = $1 AND s.archived IS NOT NULL", id)
+            .fetch_optional(&self.db)
+            .await
         {
             Ok(e) => e,
-            Err(_) => return Err(DatabaseErrors::Error),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
         };
 
-        return Ok(tasks
-            .iter()
-            .map(|e| Schedule {
-                id: e.id.clone(),
-                guild_id: e.guildid.clone(),
-                user_id: e.userid.clone(),
-                channel_id: e.channel.clone(),
-                interval: e.interval.clone(),
-                next_run: e.nextrun.clone().and_utc(),
-                created: e.created.clone().and_utc(),
-                praise: e.praise.clone(),
-                praise_name: e
-                    .praisename
-                    .clone(),
-                task: e.task.clone(),
-                task_secondary: e.tasksecondary.clone(),
+        Ok(opt.map(|e| ArchivedTask {
+            id: e.id,
+            guild_id: e.guildid,
+            user_id: e.userid,
+            task: e.task,
+            end_date: e.enddate.map(|d| d.and_utc()),
+            archived: e.archived.unwrap_or_default().and_utc(),
+            reason: e.archivereason.unwrap_or_default(),
+            timezone: e.timezone,
+        }))
+    }
+
+    async fn get_archived_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+        reason: &str,
+    ) -> Result<Vec<ArchivedTask>, DatabaseErrors> {
+        let tasks = match sqlx::query!("SELECT s.id, s.guildid, u.userid, s.task, s.enddate, s.archived, s.archivereason, u.timezone FROM schedule s INNER JOIN users u on s.userid = u.id AND s.guildid = $1 WHERE s.archivereason = $3 AND ($2::BIGINT IS NULL OR u.userid = $2) ORDER BY s.archived DESC", guild_id, user_id, reason)
+            .fetch_all(&self.db)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        Ok(tasks
+            .into_iter()
+            .map(|e| ArchivedTask {
+                id: e.id,
+                guild_id: e.guildid,
+                user_id: e.userid,
+                task: e.task,
+                end_date: e.enddate.map(|d| d.and_utc()),
+                archived: e.archived.unwrap_or_default().and_utc(),
+                reason: e.archivereason.unwrap_or_default(),
+                timezone: e.timezone,
             })
-            .collect());
+            .collect())
     }
 
-    pub async fn get_task_guild(&self, guild_id: &i64) -> Result<Vec<UserTask>, DatabaseErrors> {
+    async fn pause_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+        reason: &str,
+        now: &DateTime<Utc>,
+    ) -> Result<u64, DatabaseErrors> {
+        match sqlx::query!("UPDATE schedule s SET archived = $4, archivereason = $3 FROM users u WHERE s.userid = u.id AND s.guildid = $1 AND ($2::BIGINT IS NULL OR u.userid = $2) AND s.archived IS NULL", guild_id, user_id, reason, now.naive_utc())
+            .execute(&self.db)
+            .await
+        {
+            Ok(e) => Ok(e.rows_affected()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
 
-        let users = match self.get_users_guild(guild_id).await{
+    async fn restore_task(&self, id: &i64, now: &DateTime<Utc>) -> Result<Task, DatabaseErrors> {
+        let mut tx = match self.db.begin().await {
             Ok(e) => e,
-            Err(e) => return Err(e)
+            Err(e) => return Err(DatabaseErrors::Query(e)),
         };
 
-        println!("Found {} users", users.len());
+        let e = match sqlx::query!("SELECT s.userid AS uid, s.guildid, s.interval, s.times, s.nextrun, u.userid, u.timezone, u.archived FROM schedule s INNER JOIN users u on s.userid = u.id AND s.id = $1 AND s.archived IS NOT NULL", id)
+            .fetch_optional(&mut *tx)
+            .await
+        {
+            Ok(Some(e)) => e,
+            Ok(None) => return Err(DatabaseErrors::DoesNotExist),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
 
-        let mut res: Vec<UserTask> = Vec::new();
+        let mut owner = e.uid;
 
-        for user in users {
-            let tasks = match self.get_task_user(guild_id, &user.user_id).await{
+        if e.archived.is_some() {
+            // The user may have been added again since they were deleted
+            let active = match sqlx::query!(
+                "SELECT id FROM users WHERE guildid = $1 AND userid = $2 AND archived IS NULL",
+                e.guildid,
+                e.userid
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            {
                 Ok(e) => e,
-                Err(e) => return Err(e)
+                Err(e) => return Err(DatabaseErrors::Query(e)),
             };
 
-            println!("Found {} tasks", tasks.len());
+            match active {
+                Some(u) => owner = u.id,
+                None => {
+                    if let Err(e) =
+                        sqlx::query!("UPDATE users SET archived = NULL WHERE id = $1", e.uid)
+                            .execute(&mut *tx)
+                            .await
+                    {
+                        return Err(DatabaseErrors::Query(e));
+                    }
+                }
+            }
+        }
+
+        let mut next_run = e.nextrun.and_utc();
+        while next_run <= *now {
+            next_run = crate::util::next_occurrence(&next_run, &e.interval, &e.times, &e.timezone);
+        }
+
+        if let Err(e) = sqlx::query!("UPDATE schedule SET archived = NULL, archivereason = NULL, userid = $2, nextrun = $3 WHERE id = $1", id, owner, next_run.naive_utc())
+            .execute(&mut *tx)
+            .await
+        {
+            return Err(DatabaseErrors::Query(e));
+        }
 
-            for task in tasks {
-                res.push(task);
+        if let Err(e) = tx.commit().await {
+            return Err(DatabaseErrors::Query(e));
+        }
+
+        match self.get_task_id(id).await {
+            Ok(Some(e)) => Ok(e),
+            Ok(None) => Err(DatabaseErrors::DoesNotExist),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Permanently removes schedules and users archived before `before` and
+    /// guilds puppy was removed from before then
+    async fn purge_archived(&self, before: &DateTime<Utc>) -> Result<u64, DatabaseErrors> {
+        let deliveries = match sqlx::query!(
+            "DELETE FROM deliveries WHERE sent < $1 OR failed < $1",
+            before.naive_utc()
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(e) => e.rows_affected(),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let schedules = match sqlx::query!(
+            "DELETE FROM schedule WHERE archived < $1",
+            before.naive_utc()
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(e) => e.rows_affected(),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let users = match sqlx::query!("DELETE FROM users u WHERE (u.archived < $1 OR u.guildid IN (SELECT guildid FROM guilds WHERE removed < $1)) AND NOT EXISTS (SELECT 1 FROM schedule s WHERE s.userid = u.id)", before.naive_utc())
+            .execute(&self.db)
+            .await
+        {
+            Ok(e) => e.rows_affected(),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match sqlx::query!("DELETE FROM guilds g WHERE g.removed < $1 AND NOT EXISTS (SELECT 1 FROM users u WHERE u.guildid = g.guildid) AND NOT EXISTS (SELECT 1 FROM schedule s WHERE s.guildid = g.guildid)", before.naive_utc())
+            .execute(&self.db)
+            .await
+        {
+            Ok(e) => Ok(deliveries + schedules + users + e.rows_affected()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn enqueue_due(&self, now: &DateTime<Utc>) -> Result<u64, DatabaseErrors> {
+        let mut tx = match self.db.begin().await {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        // SKIP LOCKED lets other instances queue the remaining rows instead of waiting on ours
+        let tasks = match sqlx::query!(
+            "SELECT s.id, s.guildid, g.channel, u.userid, s.task, s.tasksecondary, u.praise, u.praisename, s.interval, s.times, u.timezone, s.created, s.nextrun, s.enddate, s.maxruns, s.runs FROM schedule s INNER JOIN users u on s.userid = u.id INNER JOIN guilds g on s.guildid = g.guildid WHERE s.archived IS NULL AND s.nextrun < $1 FOR UPDATE OF s SKIP LOCKED",
+            now.naive_utc()
+        )
+        .fetch_all(&mut *tx)
+        .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let schedules: Vec<Schedule> = tasks
+            .into_iter()
+            .map(|e| Schedule {
+                id: e.id,
+                guild_id: e.guildid,
+                user_id: e.userid,
+                channel_id: e.channel,
+                interval: e.interval,
+                times: e.times,
+                timezone: e.timezone,
+                next_run: e.nextrun.and_utc(),
+                created: e.created.and_utc(),
+                end_date: e.enddate.map(|d| d.and_utc()),
+                max_runs: e.maxruns,
+                runs: e.runs,
+                praise: e.praise,
+                praise_name: e.praisename,
+                task: e.task,
+                task_secondary: e.tasksecondary,
+            })
+            .collect();
+
+        let mut queued = 0;
+
+        for schedule in &schedules {
+            let res = match schedule.is_final_run() {
+                true => sqlx::query!(
+                    "UPDATE schedule SET runs = runs + 1, archived = $2, archivereason = 'finished' WHERE id = $1",
+                    schedule.id,
+                    now.naive_utc()
+                )
+                .execute(&mut *tx)
+                .await,
+                false => sqlx::query!(
+                    "UPDATE schedule SET nextrun = $2, runs = runs + 1 WHERE id = $1",
+                    schedule.id,
+                    crate::util::next_occurrence(
+                        &schedule.next_run,
+                        &schedule.interval,
+                        &schedule.times,
+                        &schedule.timezone
+                    )
+                    .naive_utc()
+                )
+                .execute(&mut *tx)
+                .await,
+            };
+
+            if let Err(e) = res {
+                return Err(DatabaseErrors::Query(e));
             }
+
+            match sqlx::query!(
+                "INSERT INTO deliveries (scheduleid, run, runat, nextattempt) VALUES ($1, $2, $3, $4) ON CONFLICT (scheduleid, run) DO NOTHING",
+                schedule.id,
+                schedule.runs,
+                schedule.next_run.naive_utc(),
+                now.naive_utc()
+            )
+            .execute(&mut *tx)
+            .await
+            {
+                Ok(e) => queued += e.rows_affected(),
+                Err(e) => return Err(DatabaseErrors::Query(e)),
+            };
         }
 
-        return Ok(res);
+        match tx.commit().await {
+            Ok(_) => Ok(queued),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
     }
 
-    pub async fn update_task(
-        &self,
-        id: &i64,
-        schedule: Schedule,
-    ) -> Result<Schedule, DatabaseErrors> {
-        todo!()
+    async fn next_wakeup(&self) -> Result<Option<DateTime<Utc>>, DatabaseErrors> {
+        match sqlx::query_scalar!(
+            r#"SELECT MIN(t) AS "next" FROM (SELECT MIN(nextrun) AS t FROM schedule WHERE archived IS NULL UNION ALL SELECT MIN(GREATEST(d.nextattempt, d.leaseduntil)) AS t FROM deliveries d INNER JOIN schedule s on d.scheduleid = s.id WHERE d.sent IS NULL AND d.failed IS NULL AND (s.archivereason IS NULL OR s.archivereason = 'finished')) due"#
+        )
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(e) => Ok(e.map(|d| d.and_utc())),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn count_due(&self, now: &DateTime<Utc>) -> Result<i64, DatabaseErrors> {
+        match sqlx::query_scalar!(
+            r#"SELECT (SELECT COUNT(*) FROM schedule WHERE archived IS NULL AND nextrun <= $1) + (SELECT COUNT(*) FROM deliveries d INNER JOIN schedule s on d.scheduleid = s.id WHERE d.sent IS NULL AND d.failed IS NULL AND d.runat <= $1 AND (s.archivereason IS NULL OR s.archivereason = 'finished')) AS "due!""#,
+            now.naive_utc()
+        )
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(e) => Ok(e),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
     }
 
-    pub async fn add_task(
+    async fn claim_deliveries(
         &self,
-        schedule: Task,
-    ) -> Result<Task, DatabaseErrors> {
-        let user = match self.get_user_id(&schedule.user_id).await {
+        now: &DateTime<Utc>,
+        owner: &str,
+        lease: &TimeDelta,
+    ) -> Result<Vec<Delivery>, DatabaseErrors> {
+        let mut tx = match self.db.begin().await {
             Ok(e) => e,
-            Err(e) => {
-                println!("{:?}", e);
-                return Err(e);
-            }
-        
+            Err(e) => return Err(DatabaseErrors::Query(e)),
         };
 
-        match user {
-            Some(_) => {
-                match sqlx::query!("INSERT INTO schedule(guildid, userid, task, tasksecondary, interval, nextrun) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id", schedule.guild_id, schedule.user_id, schedule.task, schedule.task_secondary, schedule.interval, schedule.next_run.naive_utc()).fetch_one(&self.db).await{
-                    Ok(e) => {
-                        return Ok(Task{
-                            id: e.id,
-                            .. schedule
-                        })
-                    }, 
-                    Err(e) => { 
-                        println!("{:?}", e);
-                        return Err(DatabaseErrors::Error);
-                    }
+        let rows = match sqlx::query!(
+            "SELECT d.id AS deliveryid, d.run, d.runat, d.attempts, s.id, s.guildid, g.channel, u.userid, s.task, s.tasksecondary, u.praise, u.praisename, s.interval, s.times, u.timezone, s.created, s.enddate, s.maxruns FROM deliveries d INNER JOIN schedule s on d.scheduleid = s.id INNER JOIN users u on s.userid = u.id INNER JOIN guilds g on s.guildid = g.guildid WHERE d.sent IS NULL AND d.failed IS NULL AND d.nextattempt <= $1 AND (d.leaseduntil IS NULL OR d.leaseduntil < $1) AND (s.archivereason IS NULL OR s.archivereason = 'finished') ORDER BY d.runat FOR UPDATE OF d SKIP LOCKED",
+            now.naive_utc()
+        )
+        .fetch_all(&mut *tx)
+        .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
 
-                };
+        let deliveries: Vec<Delivery> = rows
+            .into_iter()
+            .map(|e| Delivery {
+                id: e.deliveryid,
+                attempts: e.attempts,
+                schedule: Schedule {
+                    id: e.id,
+                    guild_id: e.guildid,
+                    user_id: e.userid,
+                    channel_id: e.channel,
+                    interval: e.interval,
+                    times: e.times,
+                    timezone: e.timezone,
+                    next_run: e.runat.and_utc(),
+                    created: e.created.and_utc(),
+                    end_date: e.enddate.map(|d| d.and_utc()),
+                    max_runs: e.maxruns,
+                    runs: e.run,
+                    praise: e.praise,
+                    praise_name: e.praisename,
+                    task: e.task,
+                    task_secondary: e.tasksecondary,
+                },
+            })
+            .collect();
 
-            },
-            None => return Err(DatabaseErrors::UserDoesNotExist)
+        let ids: Vec<i64> = deliveries.iter().map(|d| d.id).collect();
+
+        match sqlx::query!(
+            "UPDATE deliveries SET leasedby = $2, leaseduntil = $3 WHERE id = ANY($1)",
+            &ids,
+            owner,
+            (*now + *lease).naive_utc()
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => (),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match tx.commit().await {
+            Ok(_) => Ok(deliveries),
+            Err(e) => Err(DatabaseErrors::Query(e)),
         }
     }
 
-    pub async fn delete_task(&self, id: &i64) -> Result<(), DatabaseErrors> {
-        match sqlx::query!("DELETE FROM schedule WHERE id = $1", id).execute(&self.db).await {
+    async fn mark_delivered(
+        &self,
+        id: &i64,
+        message_id: &i64,
+        now: &DateTime<Utc>,
+    ) -> Result<(), DatabaseErrors> {
+        match sqlx::query!(
+            "UPDATE deliveries SET messageid = $2, sent = $3, sentby = leasedby, attempts = attempts + 1, leasedby = NULL, leaseduntil = NULL WHERE id = $1",
+            id,
+            message_id,
+            now.naive_utc()
+        )
+        .execute(&self.db)
+        .await
+        {
             Ok(_) => Ok(()),
-            Err(_) => Err(DatabaseErrors::Error)
+            Err(e) => Err(DatabaseErrors::Query(e)),
         }
     }
 
-    pub async fn delete_task_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors> {
-        match sqlx::query!("DELETE FROM schedule WHERE guildid = $1 AND userid = $2", guild_id, user_id).execute(&self.db).await {
+    async fn retry_delivery(
+        &self,
+        id: &i64,
+        retry_at: &DateTime<Utc>,
+        error: &str,
+    ) -> Result<(), DatabaseErrors> {
+        match sqlx::query!(
+            "UPDATE deliveries SET nextattempt = $2, lasterror = $3, attempts = attempts + 1, leasedby = NULL, leaseduntil = NULL WHERE id = $1",
+            id,
+            retry_at.naive_utc(),
+            error
+        )
+        .execute(&self.db)
+        .await
+        {
             Ok(_) => Ok(()),
-            Err(_) => Err(DatabaseErrors::Error)
+            Err(e) => Err(DatabaseErrors::Query(e)),
         }
     }
 
-    pub async fn incriment_task(&self, id: &i64) -> Result<(), DatabaseErrors> {
-        let opt = match sqlx::query!("SELECT * FROM schedule WHERE id = $1", id)
-            .fetch_optional(&self.db)
-            .await
+    async fn fail_delivery(
+        &self,
+        id: &i64,
+        now: &DateTime<Utc>,
+        error: &str,
+    ) -> Result<(), DatabaseErrors> {
+        match sqlx::query!(
+            "UPDATE deliveries SET failed = $2, lasterror = $3, attempts = attempts + 1, leasedby = NULL, leaseduntil = NULL WHERE id = $1",
+            id,
+            now.naive_utc(),
+            error
+        )
+        .execute(&self.db)
+        .await
         {
-            Ok(e) => e,
-            Err(_) => return Err(DatabaseErrors::Error),
-        };
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
 
-        match opt {
-            Some(_) => {
-                match sqlx::query!(
-                    "UPDATE schedule SET nextrun = (nextrun + interval) WHERE id = $1",
-                    id
-                )
-                .execute(&self.db)
-                .await
-                {
-                    Ok(_) => return Ok(()),
-                    Err(_) => return Err(DatabaseErrors::Error),
-                }
-            }
-            None => return Ok(()),
-        };
+    async fn sent_by(&self, message_id: &i64, owner: &str) -> Result<bool, DatabaseErrors> {
+        match sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM deliveries WHERE messageid = $1 AND sentby = $2) AS "sent!""#,
+            message_id,
+            owner
+        )
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(e) => Ok(e),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
     }
-    
-    /// Shifts all schedules for a user by an interval
-    pub async fn shift_schedules(&self, guild_id: &i64, user_id: &i64, interval: &PgInterval) -> Result<(), DatabaseErrors> {
+
+    async fn shift_schedules(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+        interval: &PgInterval,
+    ) -> Result<(), DatabaseErrors> {
         let uid = match self.get_user_guild(guild_id, user_id).await {
             Ok(o) => match o {
                 Some(e) => e,
-                None => return Ok(())
+                None => return Ok(()),
             },
-            Err(_) => return Err(DatabaseErrors::Error),
-
+            Err(e) => return Err(e),
         };
 
-        println!("Running query");
         match sqlx::query!(
-                "UPDATE schedule SET nextrun = (nextrun + $3) WHERE userid= $1 and guildid = $2", uid.id, guild_id, interval,
+                "UPDATE schedule SET nextrun = (nextrun + $3), times = ARRAY(SELECT t + $3 FROM unnest(times) t ORDER BY 1) WHERE userid= $1 and guildid = $2 and archived IS NULL", uid.id, guild_id, interval,
             )
             .execute(&self.db)
             .await
             {
                 Ok(_) => return Ok(()),
-                Err(_) => return Err(DatabaseErrors::Error),
+                Err(e) => return Err(DatabaseErrors::Query(e)),
             };
-        
-        }
+    }
 
-    pub async fn get_user_id(&self, id: &i64) -> Result<Option<User>, DatabaseErrors> {
-        let opt = match sqlx::query!("SELECT * FROM users where id = $1", id)
+    async fn get_user_id(&self, id: &i64) -> Result<Option<User>, DatabaseErrors> {
+        let opt = match sqlx::query!("SELECT * FROM users where id = $1 AND archived IS NULL", id)
             .fetch_optional(&self.db)
             .await
         {
             Ok(e) => e,
-            Err(_) => return Err(DatabaseErrors::Error),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
         };
 
         match opt {
             Some(e) => {
                 return Ok(Some(User {
-                    id: e.id.clone(),
-                    guild_id: e.guildid.clone(),
-                    user_id: e.userid.clone(),
+                    id: e.id,
+                    guild_id: e.guildid,
+                    user_id: e.userid,
                     praise: e.praise.clone(),
                     praise_name: e.praisename.clone(),
-                    timezone: e.timezone.clone(),
+                    timezone: e.timezone,
                 }));
             }
             None => return Ok(None),
         };
     }
 
-    pub async fn get_users_guild(&self, guild_id: &i64) -> Result<Vec<User>, DatabaseErrors> {
-        let list = match sqlx::query!("SELECT * FROM users where guildid = $1", guild_id)
-            .fetch_all(&self.db)
-            .await
+    async fn get_users_guild(&self, guild_id: &i64) -> Result<Vec<User>, DatabaseErrors> {
+        let list = match sqlx::query!(
+            "SELECT * FROM users where guildid = $1 AND archived IS NULL",
+            guild_id
+        )
+        .fetch_all(&self.db)
+        .await
         {
             Ok(e) => e,
-            Err(_) => return Err(DatabaseErrors::Error),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
         };
 
-        return Ok(list.iter().map(|e|User {
-                    id: e.id.clone(),
-                    guild_id: e.guildid.clone(),
-                    user_id: e.userid.clone(),
-                    praise: e.praise.clone(),
-                    praise_name: e.praisename.clone(),
-                    timezone: e.timezone.clone(),
-                } ).collect());
-
-            }
+        return Ok(list
+            .iter()
+            .map(|e| User {
+                id: e.id,
+                guild_id: e.guildid,
+                user_id: e.userid,
+                praise: e.praise.clone(),
+                praise_name: e.praisename.clone(),
+                timezone: e.timezone,
+            })
+            .collect());
+    }
 
-    pub async fn get_user_guild(
+    async fn get_user_guild(
         &self,
         guild_id: &i64,
         user_id: &i64,
     ) -> Result<Option<User>, DatabaseErrors> {
         let opt = match sqlx::query!(
-            "SELECT * FROM users where guildid = $1 AND userid = $2",
+            "SELECT * FROM users where guildid = $1 AND userid = $2 AND archived IS NULL",
             guild_id,
             user_id
         )
@@ -460,28 +744,25 @@ impl Database {
         .await
         {
             Ok(e) => e,
-            Err(_) => return Err(DatabaseErrors::Error),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
         };
 
         match opt {
             Some(e) => {
                 return Ok(Some(User {
-                    id: e.id.clone(),
-                    guild_id: e.guildid.clone(),
-                    user_id: e.userid.clone(),
+                    id: e.id,
+                    guild_id: e.guildid,
+                    user_id: e.userid,
                     praise: e.praise.clone(),
                     praise_name: e.praisename.clone(),
-                    timezone: e.timezone.clone(),
+                    timezone: e.timezone,
                 }));
             }
             None => return Ok(None),
         };
     }
 
-    pub async fn update_user(
-        &self,
-        user: &User,
-    ) -> Result<User, DatabaseErrors> {
+    async fn update_user(&self, user: &User) -> Result<User, DatabaseErrors> {
         let opt = match self.get_user_guild(&user.guild_id, &user.user_id).await {
             Ok(e) => e,
             Err(e) => return Err(e),
@@ -500,14 +781,14 @@ impl Database {
                 .await
                 {
                     Ok(_) => return Ok(user.clone()),
-                    Err(_) => return Err(DatabaseErrors::Error),
+                    Err(e) => return Err(DatabaseErrors::Query(e)),
                 };
             }
             None => return Err(DatabaseErrors::UserDoesNotExist),
         };
     }
 
-    pub async fn delete_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors> {
+    async fn delete_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors> {
         let opt = match self.get_user_guild(guild_id, user_id).await {
             Ok(e) => e,
             Err(e) => return Err(e),
@@ -515,27 +796,27 @@ impl Database {
 
         match opt {
             Some(e) => {
-                match sqlx::query!("DELETE FROM schedule WHERE guildid = $1 AND userid = $2", guild_id, e.id)
-                    .execute(&self.db)
-                    .await
-                {
+                match self.delete_task_user(guild_id, &e.id).await {
                     Ok(_) => (),
-                    Err(_) => return Err(DatabaseErrors::Error),
+                    Err(e) => return Err(e),
                 };
 
-                match sqlx::query!("DELETE FROM users WHERE id = $1", e.id)
-                    .execute(&self.db)
-                    .await
+                match sqlx::query!(
+                    "UPDATE users SET archived = (NOW() at time zone 'utc') WHERE id = $1",
+                    e.id
+                )
+                .execute(&self.db)
+                .await
                 {
                     Ok(_) => return Ok(()),
-                    Err(_) => return Err(DatabaseErrors::Error),
+                    Err(e) => return Err(DatabaseErrors::Query(e)),
                 };
             }
             None => return Err(DatabaseErrors::UserDoesNotExist),
         };
     }
 
-    pub async fn add_user(&self, user: &User) -> Result<User, DatabaseErrors> {
+    async fn add_user(&self, user: &User) -> Result<User, DatabaseErrors> {
         let opt = match self.get_guild(&user.guild_id).await {
             Ok(e) => e,
             Err(e) => return Err(e),
@@ -545,7 +826,7 @@ impl Database {
             Some(e) => {
                 let u = match self.get_user_guild(&user.guild_id, &user.user_id).await {
                     Ok(u) => u,
-                    Err(u) => return Err(u)
+                    Err(u) => return Err(u),
                 };
 
                 if u.is_some() {
@@ -564,15 +845,15 @@ impl Database {
                 .await
                 {
                     Ok(u) => return Ok(User{
-                        id: u.id.clone(),
-                        guild_id: e.id.clone(),
-                        user_id: user.user_id.clone(),
+                        id: u.id,
+                        guild_id: e.id,
+                        user_id: user.user_id,
                         praise_name: user.praise_name.clone(),
                         praise: user.praise.clone(),
-                        timezone: user.timezone.clone()
+                        timezone: user.timezone
                         
                     }),
-                    Err(_) => return Err(DatabaseErrors::Error),
+                    Err(e) => return Err(DatabaseErrors::Query(e)),
                 };
             }
             None => return Err(DatabaseErrors::GuildDoesNotExist),
@@ -0,0 +1,1261 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use chrono::TimeDelta;
+use sqlx::postgres::types::PgInterval;
+use sqlx::types::chrono::{DateTime, NaiveTime, Utc};
+
+use crate::repo::storage::*;
+
+#[derive(Clone)]
+struct UserRow {
+    user: User,
+    archived: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+struct ScheduleRow {
+    task: Task,
+    archived: Option<DateTime<Utc>>,
+    archive_reason: Option<String>,
+}
+
+#[derive(Clone)]
+struct DeliveryRow {
+    id: i64,
+    schedule_id: i64,
+    run: i32,
+    run_at: DateTime<Utc>,
+    attempts: i32,
+    next_attempt: DateTime<Utc>,
+    leased_by: Option<String>,
+    leased_until: Option<DateTime<Utc>>,
+    message_id: Option<i64>,
+    sent_by: Option<String>,
+    sent: Option<DateTime<Utc>>,
+    failed: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct State {
+    guilds: HashMap<i64, Guild>,
+    /// When puppy was removed from each guild it has left
+    removed: HashMap<i64, DateTime<Utc>>,
+    users: Vec<UserRow>,
+    schedules: Vec<ScheduleRow>,
+    deliveries: Vec<DeliveryRow>,
+    last_id: i64,
+}
+
+impl State {
+    fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn user(&self, id: i64) -> Option<&UserRow> {
+        self.users.iter().find(|u| u.user.id == id)
+    }
+
+    fn active_user(&self, guild_id: i64, user_id: i64) -> Option<&User> {
+        self.users
+            .iter()
+            .find(|u| {
+                u.archived.is_none() && u.user.guild_id == guild_id && u.user.user_id == user_id
+            })
+            .map(|u| &u.user)
+    }
+
+    fn user_task(&self, row: &ScheduleRow) -> Option<UserTask> {
+        let user = &self.user(row.task.user_id)?.user;
+        let t = &row.task;
+
+        Some(UserTask {
+            id: t.id,
+            guild_id: user.guild_id,
+            user_id: user.user_id,
+            task: t.task.clone(),
+            task_secondary: t.task_secondary.clone(),
+            interval: t.interval,
+            times: t.times.clone(),
+            created: t.created,
+            next_run: t.next_run,
+            end_date: t.end_date,
+            max_runs: t.max_runs,
+            runs: t.runs,
+            timezone: user.timezone,
+        })
+    }
+
+    fn schedule(&self, row: &ScheduleRow) -> Option<Schedule> {
+        let user = &self.user(row.task.user_id)?.user;
+        let guild = self.guilds.get(&row.task.guild_id)?;
+        let t = &row.task;
+
+        Some(Schedule {
+            id: t.id,
+            guild_id: t.guild_id,
+            user_id: user.user_id,
+            channel_id: guild.channel,
+            task: t.task.clone(),
+            task_secondary: t.task_secondary.clone(),
+            praise: user.praise.clone(),
+            praise_name: user.praise_name.clone(),
+            interval: t.interval,
+            times: t.times.clone(),
+            timezone: user.timezone,
+            created: t.created,
+            next_run: t.next_run,
+            end_date: t.end_date,
+            max_runs: t.max_runs,
+            runs: t.runs,
+        })
+    }
+
+    /// Whether the queued deliveries of a schedule should still go out, which
+    /// is the case for finished schedules but not deleted or paused ones
+    fn is_deliverable(row: &ScheduleRow) -> bool {
+        matches!(row.archive_reason.as_deref(), None | Some("finished"))
+    }
+
+    fn archive_user_tasks(&mut self, guild_id: i64, user_id: i64, now: DateTime<Utc>) {
+        for row in self.schedules.iter_mut().filter(|r| {
+            r.archived.is_none() && r.task.guild_id == guild_id && r.task.user_id == user_id
+        }) {
+            row.archived = Some(now);
+            row.archive_reason = Some("deleted".to_owned());
+        }
+    }
+}
+
+/// Storage kept entirely in memory, used to test code that needs a `Storage`
+/// without a running Postgres
+#[derive(Default)]
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get_guild(&self, guild: &i64) -> Result<Option<Guild>, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state.guilds.get(guild).cloned())
+    }
+
+    async fn update_guild(&self, guild: &Guild) -> Result<Guild, DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        state.guilds.insert(guild.id, guild.clone());
+
+        Ok(guild.clone())
+    }
+
+    async fn set_guild_removed(
+        &self,
+        guild_id: &i64,
+        removed: Option<&DateTime<Utc>>,
+    ) -> Result<(), DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        match removed {
+            Some(e) => state.removed.insert(*guild_id, *e),
+            None => state.removed.remove(guild_id),
+        };
+
+        Ok(())
+    }
+
+    async fn get_task_id(&self, id: &i64) -> Result<Option<Task>, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .schedules
+            .iter()
+            .find(|r| r.task.id == *id && r.archived.is_none())
+            .map(|r| r.task.clone()))
+    }
+
+    async fn get_task_user(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+    ) -> Result<Vec<UserTask>, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .schedules
+            .iter()
+            .filter(|r| r.archived.is_none())
+            .filter_map(|r| state.user_task(r))
+            .filter(|t| t.guild_id == *guild_id && t.user_id == *user_id)
+            .collect())
+    }
+
+    async fn add_task(&self, schedule: Task) -> Result<Task, DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.user(schedule.user_id) {
+            Some(u) if u.archived.is_none() => (),
+            _ => return Err(DatabaseErrors::UserDoesNotExist),
+        };
+
+        let task = Task {
+            id: state.next_id(),
+            runs: 0,
+            ..schedule
+        };
+
+        state.schedules.push(ScheduleRow {
+            task: task.clone(),
+            archived: None,
+            archive_reason: None,
+        });
+
+        Ok(task)
+    }
+
+    async fn delete_task(&self, id: &i64) -> Result<(), DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(row) = state
+            .schedules
+            .iter_mut()
+            .find(|r| r.task.id == *id && r.archived.is_none())
+        {
+            row.archived = Some(Utc::now());
+            row.archive_reason = Some("deleted".to_owned());
+        }
+
+        Ok(())
+    }
+
+    async fn delete_task_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        state.archive_user_tasks(*guild_id, *user_id, Utc::now());
+
+        Ok(())
+    }
+
+    async fn get_archived_task_id(&self, id: &i64) -> Result<Option<ArchivedTask>, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .schedules
+            .iter()
+            .find(|r| r.task.id == *id)
+            .and_then(|r| archived_task(&state, r)))
+    }
+
+    async fn get_archived_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+        reason: &str,
+    ) -> Result<Vec<ArchivedTask>, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        let mut res: Vec<ArchivedTask> = state
+            .schedules
+            .iter()
+            .filter(|r| r.task.guild_id == *guild_id)
+            .filter(|r| r.archive_reason.as_deref() == Some(reason))
+            .filter_map(|r| archived_task(&state, r))
+            .filter(|t| user_id.is_none_or(|u| t.user_id == *u))
+            .collect();
+
+        res.sort_by_key(|t| std::cmp::Reverse(t.archived));
+
+        Ok(res)
+    }
+
+    async fn pause_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+        reason: &str,
+        now: &DateTime<Utc>,
+    ) -> Result<u64, DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        let owners: Vec<i64> = state
+            .users
+            .iter()
+            .filter(|u| u.user.guild_id == *guild_id)
+            .filter(|u| user_id.is_none_or(|id| u.user.user_id == *id))
+            .map(|u| u.user.id)
+            .collect();
+
+        let mut paused = 0;
+
+        for row in state.schedules.iter_mut().filter(|r| {
+            r.archived.is_none() && r.task.guild_id == *guild_id && owners.contains(&r.task.user_id)
+        }) {
+            row.archived = Some(*now);
+            row.archive_reason = Some(reason.to_owned());
+            paused += 1;
+        }
+
+        Ok(paused)
+    }
+
+    async fn restore_task(&self, id: &i64, now: &DateTime<Utc>) -> Result<Task, DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        let row = match state
+            .schedules
+            .iter()
+            .find(|r| r.task.id == *id && r.archived.is_some())
+        {
+            Some(e) => e.clone(),
+            None => return Err(DatabaseErrors::DoesNotExist),
+        };
+
+        let owner = match state.user(row.task.user_id) {
+            Some(e) => e.clone(),
+            None => return Err(DatabaseErrors::UserDoesNotExist),
+        };
+
+        let mut owner_id = owner.user.id;
+
+        if owner.archived.is_some() {
+            match state.active_user(owner.user.guild_id, owner.user.user_id) {
+                Some(u) => owner_id = u.id,
+                None => {
+                    if let Some(u) = state.users.iter_mut().find(|u| u.user.id == owner_id) {
+                        u.archived = None;
+                    }
+                }
+            }
+        }
+
+        let mut next_run = row.task.next_run;
+        while next_run <= *now {
+            next_run = crate::util::next_occurrence(
+                &next_run,
+                &row.task.interval,
+                &row.task.times,
+                &owner.user.timezone,
+            );
+        }
+
+        let restored = match state.schedules.iter_mut().find(|r| r.task.id == *id) {
+            Some(r) => {
+                r.archived = None;
+                r.archive_reason = None;
+                r.task.user_id = owner_id;
+                r.task.next_run = next_run;
+                r.task.clone()
+            }
+            None => return Err(DatabaseErrors::DoesNotExist),
+        };
+
+        Ok(restored)
+    }
+
+    async fn purge_archived(&self, before: &DateTime<Utc>) -> Result<u64, DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        let count =
+            state.deliveries.len() + state.schedules.len() + state.users.len() + state.guilds.len();
+
+        state
+            .deliveries
+            .retain(|d| d.sent.or(d.failed).is_none_or(|s| s >= *before));
+
+        state
+            .schedules
+            .retain(|r| r.archived.is_none_or(|a| a >= *before));
+
+        let in_use: Vec<i64> = state.schedules.iter().map(|r| r.task.user_id).collect();
+
+        let gone: Vec<i64> = state
+            .removed
+            .iter()
+            .filter(|(_, r)| **r < *before)
+            .map(|(id, _)| *id)
+            .collect();
+
+        state.users.retain(|u| {
+            (u.archived.is_none_or(|a| a >= *before) && !gone.contains(&u.user.guild_id))
+                || in_use.contains(&u.user.id)
+        });
+
+        let occupied: Vec<i64> = state
+            .users
+            .iter()
+            .map(|u| u.user.guild_id)
+            .chain(state.schedules.iter().map(|r| r.task.guild_id))
+            .collect();
+
+        for id in gone.iter().filter(|id| !occupied.contains(id)) {
+            state.guilds.remove(id);
+            state.removed.remove(id);
+        }
+
+        let purged = count
+            - state.deliveries.len()
+            - state.schedules.len()
+            - state.users.len()
+            - state.guilds.len();
+
+        // Deliveries go with their schedule like the ON DELETE CASCADE in the database
+        let schedule_ids: Vec<i64> = state.schedules.iter().map(|r| r.task.id).collect();
+        state
+            .deliveries
+            .retain(|d| schedule_ids.contains(&d.schedule_id));
+
+        Ok(purged as u64)
+    }
+
+    async fn enqueue_due(&self, now: &DateTime<Utc>) -> Result<u64, DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        let schedules: Vec<Schedule> = state
+            .schedules
+            .iter()
+            .filter(|r| r.archived.is_none() && r.task.next_run < *now)
+            .filter_map(|r| state.schedule(r))
+            .collect();
+
+        let mut queued = 0;
+
+        for schedule in &schedules {
+            if let Some(row) = state
+                .schedules
+                .iter_mut()
+                .find(|r| r.task.id == schedule.id)
+            {
+                row.task.runs += 1;
+
+                if schedule.is_final_run() {
+                    row.archived = Some(*now);
+                    row.archive_reason = Some("finished".to_owned());
+                } else {
+                    row.task.next_run = crate::util::next_occurrence(
+                        &schedule.next_run,
+                        &schedule.interval,
+                        &schedule.times,
+                        &schedule.timezone,
+                    );
+                }
+            }
+
+            if state
+                .deliveries
+                .iter()
+                .any(|d| d.schedule_id == schedule.id && d.run == schedule.runs)
+            {
+                continue;
+            }
+
+            let id = state.next_id();
+            state.deliveries.push(DeliveryRow {
+                id,
+                schedule_id: schedule.id,
+                run: schedule.runs,
+                run_at: schedule.next_run,
+                attempts: 0,
+                next_attempt: *now,
+                leased_by: None,
+                leased_until: None,
+                message_id: None,
+                sent_by: None,
+                sent: None,
+                failed: None,
+            });
+            queued += 1;
+        }
+
+        Ok(queued)
+    }
+
+    async fn next_wakeup(&self) -> Result<Option<DateTime<Utc>>, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        let schedules = state
+            .schedules
+            .iter()
+            .filter(|r| r.archived.is_none())
+            .map(|r| r.task.next_run);
+
+        let deliveries = state
+            .deliveries
+            .iter()
+            .filter(|d| d.sent.is_none() && d.failed.is_none())
+            .filter(|d| {
+                state
+                    .schedules
+                    .iter()
+                    .any(|r| r.task.id == d.schedule_id && State::is_deliverable(r))
+            })
+            .map(|d| {
+                d.leased_until
+                    .map_or(d.next_attempt, |l| l.max(d.next_attempt))
+            });
+
+        Ok(schedules.chain(deliveries).min())
+    }
+
+    async fn count_due(&self, now: &DateTime<Utc>) -> Result<i64, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        let schedules = state
+            .schedules
+            .iter()
+            .filter(|r| r.archived.is_none() && r.task.next_run <= *now)
+            .count();
+
+        let deliveries = state
+            .deliveries
+            .iter()
+            .filter(|d| d.sent.is_none() && d.failed.is_none() && d.run_at <= *now)
+            .filter(|d| {
+                state
+                    .schedules
+                    .iter()
+                    .any(|r| r.task.id == d.schedule_id && State::is_deliverable(r))
+            })
+            .count();
+
+        Ok((schedules + deliveries) as i64)
+    }
+
+    async fn claim_deliveries(
+        &self,
+        now: &DateTime<Utc>,
+        owner: &str,
+        lease: &TimeDelta,
+    ) -> Result<Vec<Delivery>, DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        let mut deliveries: Vec<Delivery> = state
+            .deliveries
+            .iter()
+            .filter(|d| d.sent.is_none() && d.failed.is_none() && d.next_attempt <= *now)
+            .filter(|d| d.leased_until.is_none_or(|l| l < *now))
+            .filter_map(|d| {
+                let row = state
+                    .schedules
+                    .iter()
+                    .find(|r| r.task.id == d.schedule_id)?;
+
+                if !State::is_deliverable(row) {
+                    return None;
+                }
+
+                Some(Delivery {
+                    id: d.id,
+                    attempts: d.attempts,
+                    schedule: Schedule {
+                        next_run: d.run_at,
+                        runs: d.run,
+                        ..state.schedule(row)?
+                    },
+                })
+            })
+            .collect();
+
+        deliveries.sort_by_key(|d| d.schedule.next_run);
+
+        for delivery in &deliveries {
+            if let Some(d) = state.deliveries.iter_mut().find(|d| d.id == delivery.id) {
+                d.leased_by = Some(owner.to_owned());
+                d.leased_until = Some(*now + *lease);
+            }
+        }
+
+        Ok(deliveries)
+    }
+
+    async fn mark_delivered(
+        &self,
+        id: &i64,
+        message_id: &i64,
+        now: &DateTime<Utc>,
+    ) -> Result<(), DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(d) = state.deliveries.iter_mut().find(|d| d.id == *id) {
+            d.message_id = Some(*message_id);
+            d.sent = Some(*now);
+            d.attempts += 1;
+            d.sent_by = d.leased_by.take();
+            d.leased_until = None;
+        }
+
+        Ok(())
+    }
+
+    async fn retry_delivery(
+        &self,
+        id: &i64,
+        retry_at: &DateTime<Utc>,
+        _error: &str,
+    ) -> Result<(), DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(d) = state.deliveries.iter_mut().find(|d| d.id == *id) {
+            d.next_attempt = *retry_at;
+            d.attempts += 1;
+            d.leased_by = None;
+            d.leased_until = None;
+        }
+
+        Ok(())
+    }
+
+    async fn fail_delivery(
+        &self,
+        id: &i64,
+        now: &DateTime<Utc>,
+        _error: &str,
+    ) -> Result<(), DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(d) = state.deliveries.iter_mut().find(|d| d.id == *id) {
+            d.failed = Some(*now);
+            d.attempts += 1;
+            d.leased_by = None;
+            d.leased_until = None;
+        }
+
+        Ok(())
+    }
+
+    async fn sent_by(&self, message_id: &i64, owner: &str) -> Result<bool, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .deliveries
+            .iter()
+            .any(|d| d.message_id == Some(*message_id) && d.sent_by.as_deref() == Some(owner)))
+    }
+
+    async fn shift_schedules(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+        interval: &PgInterval,
+    ) -> Result<(), DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        let uid = match state.active_user(*guild_id, *user_id) {
+            Some(e) => e.id,
+            None => return Ok(()),
+        };
+
+        let delta = TimeDelta::microseconds(interval.microseconds);
+
+        for row in state.schedules.iter_mut().filter(|r| {
+            r.archived.is_none() && r.task.guild_id == *guild_id && r.task.user_id == uid
+        }) {
+            row.task.next_run += delta;
+            row.task.times = row
+                .task
+                .times
+                .iter()
+                .map(|t| t.overflowing_add_signed(delta).0)
+                .collect::<Vec<NaiveTime>>();
+            row.task.times.sort();
+        }
+
+        Ok(())
+    }
+
+    async fn get_user_id(&self, id: &i64) -> Result<Option<User>, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .user(*id)
+            .filter(|u| u.archived.is_none())
+            .map(|u| u.user.clone()))
+    }
+
+    async fn get_users_guild(&self, guild_id: &i64) -> Result<Vec<User>, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .users
+            .iter()
+            .filter(|u| u.archived.is_none() && u.user.guild_id == *guild_id)
+            .map(|u| u.user.clone())
+            .collect())
+    }
+
+    async fn get_user_guild(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+    ) -> Result<Option<User>, DatabaseErrors> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state.active_user(*guild_id, *user_id).cloned())
+    }
+
+    async fn update_user(&self, user: &User) -> Result<User, DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        let row = match state.users.iter_mut().find(|u| {
+            u.archived.is_none()
+                && u.user.guild_id == user.guild_id
+                && u.user.user_id == user.user_id
+        }) {
+            Some(e) => e,
+            None => return Err(DatabaseErrors::UserDoesNotExist),
+        };
+
+        row.user.praise = user.praise.clone();
+        row.user.praise_name = user.praise_name.clone();
+        row.user.timezone = user.timezone;
+
+        Ok(user.clone())
+    }
+
+    async fn delete_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        let uid = match state.active_user(*guild_id, *user_id) {
+            Some(e) => e.id,
+            None => return Err(DatabaseErrors::UserDoesNotExist),
+        };
+
+        let now = Utc::now();
+
+        state.archive_user_tasks(*guild_id, uid, now);
+
+        if let Some(u) = state.users.iter_mut().find(|u| u.user.id == uid) {
+            u.archived = Some(now);
+        }
+
+        Ok(())
+    }
+
+    async fn add_user(&self, user: &User) -> Result<User, DatabaseErrors> {
+        let mut state = self.state.lock().unwrap();
+
+        if !state.guilds.contains_key(&user.guild_id) {
+            return Err(DatabaseErrors::GuildDoesNotExist);
+        }
+
+        if state.active_user(user.guild_id, user.user_id).is_some() {
+            return Err(DatabaseErrors::UserAlreadyExists);
+        }
+
+        let user = User {
+            id: state.next_id(),
+            ..user.clone()
+        };
+
+        state.users.push(UserRow {
+            user: user.clone(),
+            archived: None,
+        });
+
+        Ok(user)
+    }
+}
+
+fn archived_task(state: &State, row: &ScheduleRow) -> Option<ArchivedTask> {
+    let user = &state.user(row.task.user_id)?.user;
+
+    Some(ArchivedTask {
+        id: row.task.id,
+        guild_id: row.task.guild_id,
+        user_id: user.user_id,
+        task: row.task.task.clone(),
+        end_date: row.task.end_date,
+        archived: row.archived?,
+        reason: row.archive_reason.clone().unwrap_or_default(),
+        timezone: user.timezone,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::repo::testing::*;
+
+    #[tokio::test]
+    async fn add_user_needs_guild_and_is_unique() {
+        let db = MemoryStorage::new();
+        let user = User {
+            id: 0,
+            guild_id: GUILD,
+            user_id: MEMBER,
+            praise: "pats".to_owned(),
+            praise_name: "good pup".to_owned(),
+            timezone: interval(TimeDelta::zero()),
+        };
+
+        assert!(matches!(
+            db.add_user(&user).await,
+            Err(DatabaseErrors::GuildDoesNotExist)
+        ));
+
+        db.update_guild(&Guild {
+            id: GUILD,
+            channel: CHANNEL,
+        })
+        .await
+        .unwrap();
+
+        assert!(db.add_user(&user).await.is_ok());
+        assert!(matches!(
+            db.add_user(&user).await,
+            Err(DatabaseErrors::UserAlreadyExists)
+        ));
+    }
+
+    async fn enqueue(db: &MemoryStorage, now: &str) -> u64 {
+        db.enqueue_due(&at(now)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn enqueue_only_queues_due_tasks() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::hours(10)).await;
+
+        let due = db
+            .add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+        db.add_task(task(&user, at("2025-01-01T12:00:00Z")))
+            .await
+            .unwrap();
+
+        assert_eq!(enqueue(&db, "2025-01-01T09:00:00Z").await, 1);
+
+        let deliveries = claim(&db, &at("2025-01-01T09:00:00Z"), "a").await;
+
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].schedule.id, due.id);
+        assert_eq!(deliveries[0].schedule.channel_id, CHANNEL);
+        assert_eq!(deliveries[0].schedule.user_id, MEMBER);
+        assert_eq!(
+            deliveries[0].schedule.timezone,
+            interval(TimeDelta::hours(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn enqueue_moves_by_interval() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let t = db
+            .add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        enqueue(&db, "2025-01-01T09:00:00Z").await;
+
+        let t = db.get_task_id(&t.id).await.unwrap().unwrap();
+        assert_eq!(t.next_run, at("2025-01-02T08:00:00Z"));
+        assert_eq!(t.runs, 1);
+
+        // The delivery keeps the run it was queued for
+        let deliveries = claim(&db, &at("2025-01-01T09:00:00Z"), "a").await;
+        assert_eq!(deliveries[0].schedule.next_run, at("2025-01-01T08:00:00Z"));
+        assert_eq!(deliveries[0].schedule.runs, 0);
+    }
+
+    #[tokio::test]
+    async fn enqueue_walks_through_times_of_day() {
+        // 08:00, 14:00 and 20:00 in UTC+10
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::hours(10)).await;
+
+        let mut new = task(&user, at("2024-12-31T22:00:00Z"));
+        new.times = vec![
+            NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(14, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+        ];
+        let t = db.add_task(new).await.unwrap();
+
+        let mut runs = Vec::new();
+        for now in [
+            "2024-12-31T22:01:00Z",
+            "2025-01-01T04:01:00Z",
+            "2025-01-01T10:01:00Z",
+        ] {
+            assert_eq!(enqueue(&db, now).await, 1);
+            runs.push(db.get_task_id(&t.id).await.unwrap().unwrap().next_run);
+        }
+
+        assert_eq!(
+            runs,
+            vec![
+                at("2025-01-01T04:00:00Z"),
+                at("2025-01-01T10:00:00Z"),
+                at("2025-01-01T22:00:00Z"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn max_runs_archives_task() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let mut new = task(&user, at("2025-01-01T08:00:00Z"));
+        new.max_runs = Some(2);
+        let t = db.add_task(new).await.unwrap();
+
+        assert_eq!(enqueue(&db, "2025-01-03T00:00:00Z").await, 1);
+        assert_eq!(enqueue(&db, "2025-01-03T00:00:00Z").await, 1);
+
+        assert!(db.get_task_id(&t.id).await.unwrap().is_none());
+        assert_eq!(enqueue(&db, "2030-01-01T00:00:00Z").await, 0);
+
+        let archived = db.get_archived_task_id(&t.id).await.unwrap().unwrap();
+        assert_eq!(archived.reason, "finished");
+
+        // Only the last delivery says it is the last one
+        let deliveries = claim(&db, &at("2025-01-03T00:00:00Z"), "a").await;
+        assert_eq!(deliveries.len(), 2);
+        assert!(!deliveries[0].schedule.is_final_run());
+        assert!(deliveries[1].schedule.is_final_run());
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_leased_until_sent() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        db.add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        enqueue(&db, "2025-01-01T09:00:00Z").await;
+
+        let delivery = claim(&db, &at("2025-01-01T09:00:00Z"), "a").await[0].clone();
+
+        // Nobody can send it while the lease is held
+        assert!(claim(&db, &at("2025-01-01T09:01:00Z"), "b")
+            .await
+            .is_empty());
+
+        // A lease that ran out can be taken over
+        let taken = claim(&db, &at("2025-01-01T09:06:00Z"), "b").await;
+        assert_eq!(taken[0].id, delivery.id);
+
+        db.mark_delivered(&delivery.id, &1234, &at("2025-01-01T09:06:00Z"))
+            .await
+            .unwrap();
+        assert!(db.sent_by(&1234, "b").await.unwrap());
+        assert!(!db.sent_by(&1234, "a").await.unwrap());
+        assert!(claim(&db, &at("2025-01-01T10:00:00Z"), "a")
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_retried() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        db.add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        enqueue(&db, "2025-01-01T09:00:00Z").await;
+
+        let delivery = claim(&db, &at("2025-01-01T09:00:00Z"), "a").await[0].clone();
+        assert_eq!(delivery.attempts, 0);
+
+        db.retry_delivery(&delivery.id, &at("2025-01-01T09:10:00Z"), "timed out")
+            .await
+            .unwrap();
+
+        assert!(claim(&db, &at("2025-01-01T09:05:00Z"), "a")
+            .await
+            .is_empty());
+
+        let retried = claim(&db, &at("2025-01-01T09:10:00Z"), "a").await;
+        assert_eq!(retried[0].id, delivery.id);
+        assert_eq!(retried[0].attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn due_runs_are_counted_until_sent() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        db.add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        assert_eq!(db.count_due(&at("2025-01-01T07:59:00Z")).await.unwrap(), 0);
+        assert_eq!(db.count_due(&at("2025-01-01T08:00:00Z")).await.unwrap(), 1);
+
+        // Still due once queued, until it has been sent
+        assert_eq!(enqueue(&db, "2025-01-01T08:30:00Z").await, 1);
+        assert_eq!(db.count_due(&at("2025-01-01T08:30:00Z")).await.unwrap(), 1);
+
+        let delivery = claim(&db, &at("2025-01-01T08:30:00Z"), "a").await[0].clone();
+        db.mark_delivered(&delivery.id, &1, &at("2025-01-01T08:30:00Z"))
+            .await
+            .unwrap();
+        assert_eq!(db.count_due(&at("2025-01-01T08:30:00Z")).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_given_up_on() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        db.add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        enqueue(&db, "2025-01-01T09:00:00Z").await;
+
+        let delivery = claim(&db, &at("2025-01-01T09:00:00Z"), "a").await[0].clone();
+        db.fail_delivery(&delivery.id, &at("2025-01-01T09:00:00Z"), "missing access")
+            .await
+            .unwrap();
+
+        assert!(claim(&db, &at("2025-01-01T10:00:00Z"), "a")
+            .await
+            .is_empty());
+        assert_eq!(
+            db.next_wakeup().await.unwrap(),
+            Some(at("2025-01-02T08:00:00Z"))
+        );
+    }
+
+    #[tokio::test]
+    async fn deleted_task_is_not_delivered() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let t = db
+            .add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        enqueue(&db, "2025-01-01T09:00:00Z").await;
+        db.delete_task(&t.id).await.unwrap();
+
+        assert!(claim(&db, &at("2025-01-01T09:00:00Z"), "a")
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn end_date_archives_task() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let mut new = task(&user, at("2025-01-01T08:00:00Z"));
+        new.end_date = Some(at("2025-01-02T00:00:00Z"));
+        let t = db.add_task(new).await.unwrap();
+
+        enqueue(&db, "2025-01-01T09:00:00Z").await;
+        assert!(db.get_task_id(&t.id).await.unwrap().is_none());
+
+        // The final run is still delivered after the task is archived
+        let deliveries = claim(&db, &at("2025-01-01T09:00:00Z"), "a").await;
+        assert!(deliveries[0].schedule.is_final_run());
+    }
+
+    #[tokio::test]
+    async fn deleted_task_can_be_restored() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let t = db
+            .add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        db.delete_task(&t.id).await.unwrap();
+
+        assert!(db.get_task_user(&GUILD, &MEMBER).await.unwrap().is_empty());
+
+        let deleted = db.get_deleted_tasks(&GUILD, Some(&MEMBER)).await.unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].id, t.id);
+
+        let restored = db
+            .restore_task(&t.id, &at("2025-01-05T12:00:00Z"))
+            .await
+            .unwrap();
+
+        // Missed occurrences are skipped rather than sent all at once
+        assert_eq!(restored.next_run, at("2025-01-06T08:00:00Z"));
+        assert_eq!(db.get_task_user(&GUILD, &MEMBER).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn deleted_user_is_restored_with_task() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let t = db
+            .add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        db.delete_user(&GUILD, &MEMBER).await.unwrap();
+
+        assert!(db.get_user_guild(&GUILD, &MEMBER).await.unwrap().is_none());
+        assert!(db.get_task_id(&t.id).await.unwrap().is_none());
+
+        db.restore_task(&t.id, &Utc::now()).await.unwrap();
+
+        assert!(db.get_user_guild(&GUILD, &MEMBER).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn purge_removes_old_archived_rows() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let t = db
+            .add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        db.delete_user(&GUILD, &MEMBER).await.unwrap();
+
+        assert_eq!(
+            db.purge_archived(&(Utc::now() - TimeDelta::days(30)))
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            db.purge_archived(&(Utc::now() + TimeDelta::minutes(1)))
+                .await
+                .unwrap(),
+            2
+        );
+        assert!(db.get_archived_task_id(&t.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn paused_tasks_are_held_back_and_resumed() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let t = db
+            .add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        enqueue(&db, "2025-01-01T09:00:00Z").await;
+
+        assert_eq!(
+            db.pause_tasks(
+                &GUILD,
+                Some(&MEMBER),
+                PAUSED_CHANNEL_DELETED,
+                &at("2025-01-01T09:00:00Z")
+            )
+            .await
+            .unwrap(),
+            1
+        );
+
+        // Paused tasks aren't run, queued or offered for /restoreschedule
+        assert!(db.get_task_user(&GUILD, &MEMBER).await.unwrap().is_empty());
+        assert!(claim(&db, &at("2025-01-01T09:00:00Z"), "a")
+            .await
+            .is_empty());
+        assert!(db.get_deleted_tasks(&GUILD, None).await.unwrap().is_empty());
+
+        // Only the matching reason resumes them
+        let now = at("2025-01-03T09:00:00Z");
+        assert_eq!(
+            db.resume_tasks(&GUILD, None, PAUSED_GUILD_REMOVED, &now)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            db.resume_tasks(&GUILD, None, PAUSED_CHANNEL_DELETED, &now)
+                .await
+                .unwrap(),
+            1
+        );
+
+        let restored = db.get_task_id(&t.id).await.unwrap().unwrap();
+        assert!(restored.next_run > now);
+    }
+
+    #[tokio::test]
+    async fn removed_guild_is_purged() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        db.add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        db.set_guild_removed(&GUILD, Some(&now)).await.unwrap();
+        db.pause_tasks(&GUILD, None, PAUSED_GUILD_REMOVED, &now)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            db.purge_archived(&(now - TimeDelta::days(30)))
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            db.purge_archived(&(now + TimeDelta::minutes(1)))
+                .await
+                .unwrap(),
+            3
+        );
+        assert!(db.get_guild(&GUILD).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn shift_moves_next_run_and_times() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let mut new = task(&user, at("2025-01-01T08:00:00Z"));
+        new.times = vec![
+            NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(23, 0, 0).unwrap(),
+        ];
+        let t = db.add_task(new).await.unwrap();
+
+        db.shift_schedules(&GUILD, &MEMBER, &interval(TimeDelta::hours(2)))
+            .await
+            .unwrap();
+
+        let t = db.get_task_id(&t.id).await.unwrap().unwrap();
+        assert_eq!(t.next_run, at("2025-01-01T10:00:00Z"));
+        assert_eq!(
+            t.times,
+            vec![
+                NaiveTime::from_hms_opt(1, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn guild_tasks_cover_every_user() {
+        let (db, user) = setup(MemoryStorage::new(), TimeDelta::zero()).await;
+
+        let other = db
+            .add_user(&User {
+                user_id: MEMBER + 1,
+                ..user.clone()
+            })
+            .await
+            .unwrap();
+
+        db.add_task(task(&user, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+        db.add_task(task(&other, at("2025-01-01T08:00:00Z")))
+            .await
+            .unwrap();
+
+        assert_eq!(db.get_task_guild(&GUILD).await.unwrap().len(), 2);
+    }
+}
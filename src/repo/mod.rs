@@ -1,2 +1,33 @@
+use std::sync::Arc;
+
 pub mod database;
+#[cfg(test)]
+pub mod memory;
 pub mod schedule;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod storage;
+#[cfg(test)]
+pub mod testing;
+
+use crate::repo::storage::{DatabaseErrors, Storage};
+
+/// Connects to the backend matching the scheme of `database_url`,
+/// `sqlite:` urls need the `sqlite` feature and everything else is Postgres
+pub async fn connect(database_url: String) -> Result<Arc<dyn Storage>, DatabaseErrors> {
+    if database_url.starts_with("sqlite:") {
+        #[cfg(feature = "sqlite")]
+        return match sqlite::SqliteDatabase::new(database_url).await {
+            Ok(e) => Ok(Arc::new(e)),
+            Err(e) => Err(e),
+        };
+
+        #[cfg(not(feature = "sqlite"))]
+        return Err(DatabaseErrors::UnsupportedDatabase);
+    }
+
+    match database::Database::new(database_url).await {
+        Ok(e) => Ok(Arc::new(e)),
+        Err(e) => Err(e),
+    }
+}
@@ -1,360 +1,1839 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+use futures::stream::{self, StreamExt};
+
 use tokio::spawn;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tokio::time::{sleep, Duration};
 
+use tracing::Instrument;
+
 use chrono;
 
 use sqlx::types::chrono::{DateTime, Utc};
 
 use poise::serenity_prelude as serenity;
 
-use crate::repo::database::*;
+use crate::clock::Clock;
+use crate::messenger::{Messenger, MessengerError};
+use crate::monitoring;
+use crate::repo::storage::*;
 use crate::{Context, Data, Error};
 
+/// One occurrence of a schedule puppy is waiting on, from the reminder through
+/// every nag sent about it
 #[derive(Clone)]
 pub struct Message {
+    /// The reminder that started the occurrence, it is kept under this id
     message: serenity::MessageId,
+    /// Nags sent since, reacting to any of them counts the same as the reminder
+    nags: Vec<u64>,
+    /// When the reminder or the latest nag went out
     datetime: DateTime<Utc>,
     guild: i64,
     channel: i64,
     schedule: Schedule,
+    /// Message and user of each reaction, kept up to date by reaction events
+    reactions: HashSet<(u64, u64)>,
+    /// Set once someone has reacted and been praised for it
+    acknowledged: Option<Acknowledgement>,
+    /// Open from the delivery until puppy stops tracking the occurrence
+    span: tracing::Span,
+}
+
+impl Message {
+    /// Whether `message_id` is the reminder or one of its nags
+    fn is_for(&self, message_id: u64) -> bool {
+        self.message.get() == message_id || self.nags.contains(&message_id)
+    }
+}
+
+/// The occurrence a reminder or nag belongs to
+fn occurrence(messages: &mut HashMap<u64, Message>, message_id: u64) -> Option<&mut Message> {
+    messages.values_mut().find(|v| v.is_for(message_id))
 }
 
+#[derive(Clone)]
+struct Acknowledgement {
+    at: DateTime<Utc>,
+    /// The praise reply, so it can be taken back
+    praise: Option<u64>,
+    /// Someone said done with a reply or /done, taking a reaction off doesn't undo that
+    done: bool,
+}
+
+/// How long puppy waits for a reaction before nagging
+const NAG_DELAY: chrono::TimeDelta = chrono::TimeDelta::minutes(60);
+
+/// How long a reaction can be taken off again to undo an accidental acknowledgement
+const UNDO_GRACE: chrono::TimeDelta = chrono::TimeDelta::minutes(5);
+
+/// How long other instances keep away from deliveries this instance has claimed.
+/// Kept within the few minutes Discord remembers a nonce, so a delivery taken
+/// over after a crash mid send is not posted twice.
+pub(crate) const LEASE: chrono::TimeDelta = chrono::TimeDelta::minutes(2);
+
+/// How long puppy waits before first trying a failed delivery again
+const RETRY_DELAY: chrono::TimeDelta = chrono::TimeDelta::minutes(1);
+
+/// Longest puppy waits between tries, however many have failed
+const MAX_RETRY_DELAY: chrono::TimeDelta = chrono::TimeDelta::hours(1);
+
+/// How many times puppy tries to send a reminder before giving up on it
+const MAX_ATTEMPTS: i32 = 6;
+
+/// How many unanswered reminders are checked on at once
+const CHECK_CONCURRENCY: usize = 8;
+
+/// Longest the scheduler sleeps, in case another instance changed the schedule
+const MAX_SLEEP: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+
+/// Shortest the scheduler sleeps, so a run that is due but not yet sendable
+/// does not spin the loop
+const MIN_SLEEP: chrono::TimeDelta = chrono::TimeDelta::seconds(1);
+
 pub struct Scheduler {
-    db: Database,
+    db: Arc<dyn Storage>,
+    messenger: Arc<dyn Messenger>,
+    clock: Arc<dyn Clock>,
     active_messages: Arc<Mutex<HashMap<u64, Message>>>,
     message_map: Arc<Mutex<HashMap<i64, u64>>>,
-    http: Arc<serenity::http::Http>,
+    /// Channels the guild owner has already been told reminders can't get to
+    undeliverable: Mutex<HashSet<i64>>,
+    tailwag: String,
+    /// Identifies this instance when leasing deliveries
+    instance: String,
+    /// Notified by commands that add, restore or shift a schedule
+    wakeup: Arc<Notify>,
 }
 
 impl Scheduler {
-    pub fn start(
-        db: Database,
-        http: Arc<serenity::http::Http>,
-        tailwag: String,
+    pub fn new(
+        db: Arc<dyn Storage>,
+        messenger: Arc<dyn Messenger>,
+        clock: Arc<dyn Clock>,
         active_messages: Arc<Mutex<HashMap<u64, Message>>>,
-    ) {
-        let db_clone = db.clone();
-        let http_clone = http.clone();
-        let tailwag_clone = tailwag.clone();
-        let active_messages_clone = active_messages.clone();
+        tailwag: String,
+        instance: String,
+        wakeup: Arc<Notify>,
+    ) -> Scheduler {
+        Scheduler {
+            db,
+            messenger,
+            clock,
+            active_messages,
+            message_map: Arc::new(Mutex::new(HashMap::new())),
+            undeliverable: Mutex::new(HashSet::new()),
+            tailwag,
+            instance,
+            wakeup,
+        }
+    }
+
+    pub fn start(self, archive_retention: chrono::TimeDelta) {
+        let scheduler = Arc::new(self);
+        let scheduler_check = scheduler.clone();
+        let scheduler_purge = scheduler.clone();
+
+        tokio::spawn(async move {
+            loop {
+                scheduler.process_schedule().await;
 
-        let message_map: Arc<Mutex<HashMap<i64, u64>>> = Arc::new(Mutex::new(HashMap::new()));
+                let wait = scheduler.time_until_next_run().await;
 
-        let message_map_clone = message_map.clone();
+                tokio::select! {
+                    _ = sleep(wait) => (),
+                    _ = scheduler.wakeup.notified() => (),
+                }
+            }
+        });
 
         tokio::spawn(async move {
             loop {
-                Scheduler::process_schedule(
-                    &db_clone,
-                    &active_messages_clone,
-                    &message_map_clone,
-                    &http_clone,
-                    &tailwag_clone,
-                )
-                .await;
+                scheduler_check.check_messages().await;
                 sleep(Duration::from_secs(60)).await;
             }
         });
 
         tokio::spawn(async move {
             loop {
-                Scheduler::check_messages(&active_messages, &message_map, &http).await;
-                sleep(Duration::from_secs(60)).await;
+                scheduler_purge.purge_archived(archive_retention).await;
+                sleep(Duration::from_secs(60 * 60)).await;
             }
         });
     }
 
-    async fn process_schedule(
-        db: &Database,
-        active_messages: &Arc<Mutex<HashMap<u64, Message>>>,
-        message_map: &Arc<Mutex<HashMap<i64, u64>>>,
-        http: &Arc<serenity::http::Http>,
-        tailwag: &String,
-    ) {
-        let schedules: Vec<Schedule> = match db.get_task_nextrun(None).await {
+    async fn purge_archived(&self, retention: chrono::TimeDelta) {
+        match self
+            .db
+            .purge_archived(&(self.clock.now() - retention))
+            .await
+        {
+            Ok(0) => (),
+            Ok(e) => tracing::info!(rows = e, "purged archived rows"),
+            Err(e) => tracing::error!(error = %e, "cannot purge archived rows"),
+        }
+    }
+
+    /// How long to sleep until the next task falls due or a delivery can be retried
+    async fn time_until_next_run(&self) -> Duration {
+        let wait = match self.db.next_wakeup().await {
+            Ok(Some(e)) => (e - self.clock.now()).clamp(MIN_SLEEP, MAX_SLEEP),
+            Ok(None) => MAX_SLEEP,
+            Err(e) => {
+                tracing::error!(error = %e, "cannot fetch next run");
+                RETRY_DELAY
+            }
+        };
+
+        match wait.to_std() {
+            Ok(e) => e,
+            Err(_) => Duration::from_secs(1),
+        }
+    }
+
+    async fn process_schedule(&self) {
+        match self.db.enqueue_due(&self.clock.now()).await {
+            Ok(0) => (),
+            Ok(e) => tracing::debug!(count = e, "queued reminders"),
+            Err(e) => tracing::error!(error = %e, "cannot queue due reminders"),
+        };
+
+        self.send_deliveries().await;
+
+        match self.db.count_due(&self.clock.now()).await {
+            Ok(e) => metrics::gauge!(monitoring::DUE_UNSENT).set(e as f64),
+            Err(e) => tracing::error!(error = %e, "cannot count due reminders"),
+        };
+    }
+
+    async fn send_deliveries(&self) {
+        let deliveries: Vec<Delivery> = match self
+            .db
+            .claim_deliveries(&self.clock.now(), &self.instance, &LEASE)
+            .await
+        {
             Ok(e) => e,
-            Err(_) => {
-                println!("Cannot fetch schedule due to database error");
+            Err(e) => {
+                tracing::error!(error = %e, "cannot fetch deliveries");
                 return;
             }
         };
 
-        for schedule in schedules {
-            let guild = serenity::GuildId::from(schedule.guild_id as u64);
-
-            let channel = match guild.channels(&http).await {
-                Ok(e) => match e.get(&serenity::ChannelId::from(schedule.channel_id as u64)) {
-                    Some(c) => c.clone(),
-                    None => {
-                        println!(
-                            "Cannot find channel {} in guild {}",
-                            schedule.channel_id, schedule.guild_id
-                        );
-                        continue;
-                    }
-                },
-                Err(_) => {
-                    println!("Cannot fetch channels from guild {}", schedule.guild_id);
-                    continue;
-                }
-            };
+        for delivery in deliveries {
+            let span = tracing::info_span!(
+                "reminder",
+                schedule = delivery.schedule.id,
+                guild = delivery.schedule.guild_id,
+                user = delivery.schedule.user_id,
+                delivery = delivery.id,
+                message = tracing::field::Empty,
+            );
 
-            let user = serenity::UserId::from(schedule.user_id as u64);
-
-            let message = serenity::MessageBuilder::new()
-                .push("Reminder pup paws at you ")
-                .mention(&user)
-                .push(format!("{}\n", tailwag))
-                .push("It's time for you to ")
-                .push_bold(format!("{}\n", schedule.task))
-                .push(format!(
-                    "Please react once you've {}",
-                    schedule.task_secondary
-                ))
-                .build();
-
-            match channel.say(&http, &message).await {
-                Ok(e) => {
-                    let mut message_map_lock = message_map.lock().await;
-                    let mut message_lock = active_messages.lock().await;
-
-                    match message_map_lock.get(&schedule.id) {
-                        Some(e) => {
-                            message_lock.remove(e);
-                        }
-                        None => (),
-                    };
+            self.deliver(delivery).instrument(span).await;
+        }
+    }
 
-                    let message_id: serenity::MessageId = e.into();
+    /// Sends one claimed delivery, recording the outcome so it is either tracked
+    /// for a reaction or retried later
+    async fn deliver(&self, delivery: Delivery) {
+        // The occurrence keeps the span open until puppy stops waiting on it
+        let span = tracing::Span::current();
+        let schedule = delivery.schedule;
+        let user = serenity::UserId::from(schedule.user_id as u64);
 
-                    message_map_lock.insert(schedule.id.clone(), message_id.get());
+        let mut message = serenity::MessageBuilder::new();
+        message
+            .push("Reminder pup paws at you ")
+            .mention(&user)
+            .push(format!("{}\n", self.tailwag))
+            .push("It's time for you to ")
+            .push_bold(format!("{}\n", schedule.task))
+            .push(format!(
+                "Please react once you've {}",
+                schedule.task_secondary
+            ));
 
-                    message_lock.insert(
-                        message_id.get(),
-                        Message {
-                            message: message_id,
-                            datetime: Utc::now(),
-                            guild: schedule.guild_id.clone(),
-                            channel: schedule.channel_id.clone(),
-                            schedule: schedule.clone(),
-                        },
-                    );
+        if schedule.is_final_run() {
+            message.push(
+                "\nThis is the last time puppy will remind you about this, you did so well!!",
+            );
+        }
+
+        let message = message.build();
+
+        match self
+            .messenger
+            .send(
+                schedule.guild_id,
+                schedule.channel_id,
+                message,
+                Some(format!("d{}", delivery.id)),
+            )
+            .await
+        {
+            Ok(e) => {
+                self.undeliverable.lock().await.remove(&schedule.channel_id);
+
+                span.record("message", e);
+                tracing::info!(attempts = delivery.attempts + 1, "reminder sent");
+
+                metrics::counter!(monitoring::REMINDERS_SENT).increment(1);
+                metrics::gauge!(monitoring::SCHEDULER_LAG)
+                    .set((self.clock.now() - schedule.next_run).num_milliseconds() as f64 / 1000.0);
 
-                    match db.incriment_task(&schedule.id).await {
-                        Ok(e) => continue,
-                        Err(_) => {
-                            println!("Cannot incriment task {}", schedule.id);
-                            continue;
+                match self
+                    .db
+                    .mark_delivered(&delivery.id, &(e as i64), &self.clock.now())
+                    .await
+                {
+                    Ok(_) => (),
+                    Err(e) => tracing::error!(error = %e, "cannot mark delivery as sent"),
+                };
+
+                let mut message_map_lock = self.message_map.lock().await;
+                let mut message_lock = self.active_messages.lock().await;
+
+                match message_map_lock.get(&schedule.id) {
+                    Some(e) => {
+                        if let Some(old) = message_lock.remove(e) {
+                            if old.acknowledged.is_none() {
+                                metrics::counter!(monitoring::MISSES).increment(1);
+                                tracing::info!(
+                                    parent: &old.span,
+                                    "reminder missed, replaced by the next one"
+                                );
+                            }
                         }
                     }
+                    None => (),
+                };
+
+                message_map_lock.insert(schedule.id, e);
+
+                message_lock.insert(
+                    e,
+                    Message {
+                        message: serenity::MessageId::from(e),
+                        nags: Vec::new(),
+                        datetime: self.clock.now(),
+                        guild: schedule.guild_id,
+                        channel: schedule.channel_id,
+                        schedule: schedule.clone(),
+                        reactions: HashSet::new(),
+                        acknowledged: None,
+                        span: span.clone(),
+                    },
+                );
+
+                if schedule.is_final_run() {
+                    tracing::info!("task has finished and been archived");
                 }
-                Err(e) => {
-                    println!(
-                        "Cannot send message to channel {} in guild {}",
-                        schedule.channel_id, schedule.guild_id
+            }
+            Err(e) => {
+                metrics::counter!(monitoring::DELIVERY_FAILURES, "kind" => e.kind()).increment(1);
+
+                let attempts = delivery.attempts + 1;
+
+                if e.is_permanent() || attempts >= MAX_ATTEMPTS {
+                    tracing::error!(
+                        error = %e,
+                        channel = schedule.channel_id,
+                        attempts,
+                        "giving up on delivery"
                     );
-                    println!("{:?}", e);
-                    continue;
+
+                    match self
+                        .db
+                        .fail_delivery(&delivery.id, &self.clock.now(), &e.to_string())
+                        .await
+                    {
+                        Ok(_) => (),
+                        Err(e) => tracing::error!(error = %e, "cannot mark delivery as failed"),
+                    };
+
+                    self.report_undeliverable(&schedule, &e).await;
+                    return;
+                }
+
+                tracing::warn!(
+                    error = %e,
+                    channel = schedule.channel_id,
+                    attempts,
+                    "cannot send reminder, will retry"
+                );
+
+                match self
+                    .db
+                    .retry_delivery(
+                        &delivery.id,
+                        &(self.clock.now() + Scheduler::retry_delay(delivery.attempts)),
+                        &e.to_string(),
+                    )
+                    .await
+                {
+                    Ok(_) => (),
+                    Err(e) => tracing::error!(error = %e, "cannot reschedule delivery"),
+                };
+            }
+        }
+    }
+
+    /// How long to wait after a failed delivery, doubling with every attempt
+    fn retry_delay(attempts: i32) -> chrono::TimeDelta {
+        match 2i32.checked_pow(attempts as u32) {
+            Some(e) => (RETRY_DELAY * e).min(MAX_RETRY_DELAY),
+            None => MAX_RETRY_DELAY,
+        }
+    }
+
+    /// Lets the guild owner know reminders can't get to a channel, once until
+    /// the channel works again
+    async fn report_undeliverable(&self, schedule: &Schedule, error: &MessengerError) {
+        if !self.undeliverable.lock().await.insert(schedule.channel_id) {
+            return;
+        }
+
+        let message = serenity::MessageBuilder::new()
+            .push("Puppy tried really hard but can't deliver reminders to ")
+            .channel(serenity::ChannelId::from(schedule.channel_id as u64))
+            .push(format!(" anymore ({})\n", error))
+            .push("Please make sure puppy can still see and post in the channel or pick a new one with /setchannel")
+            .build();
+
+        match self
+            .messenger
+            .message_owner(schedule.guild_id, message)
+            .await
+        {
+            Ok(_) => (),
+            Err(e) => tracing::warn!(
+                error = %e,
+                guild = schedule.guild_id,
+                channel = schedule.channel_id,
+                "cannot tell the guild owner about the channel"
+            ),
+        };
+    }
+
+    /// Whether a reminder has gone unanswered long enough to nag about it
+    fn needs_nag(message: &Message, now: DateTime<Utc>) -> bool {
+        message.datetime + NAG_DELAY < now
+    }
+
+    async fn check_messages(&self) {
+        let now = self.clock.now();
+
+        // Copy out what needs a nag so reactions aren't held up while it is sent
+        let due: Vec<Message> = self
+            .active_messages
+            .lock()
+            .await
+            .values()
+            .filter(|v| v.acknowledged.is_none() && Scheduler::needs_nag(v, now))
+            .cloned()
+            .collect();
+
+        let nagged: Vec<(Message, Option<u64>)> = stream::iter(due)
+            .map(|v| self.nag(v))
+            .buffer_unordered(CHECK_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut messages = self.active_messages.lock().await;
+
+        for (reminder, nag) in nagged {
+            let e = match messages.get_mut(&reminder.message.get()) {
+                Some(e) if e.acknowledged.is_none() => e,
+                // Acknowledged or replaced by a newer reminder while the nag was sent
+                _ => continue,
+            };
+
+            match nag {
+                Some(nag) => {
+                    e.nags.push(nag);
+                    e.datetime = now;
                 }
+                None => {
+                    messages.remove(&reminder.message.get());
+                }
+            };
+        }
+
+        // Acknowledged reminders are only kept around until they would have been nagged
+        messages.retain(|_, v| !(v.acknowledged.is_some() && Scheduler::needs_nag(v, now)));
+    }
+
+    /// Nags about a reminder nobody has reacted to. Returns the reminder along
+    /// with the id of the nag, if it could be sent.
+    async fn nag(&self, v: Message) -> (Message, Option<u64>) {
+        let user = serenity::UserId::from(v.schedule.user_id as u64);
+
+        let message = serenity::MessageBuilder::new()
+            .mention(&user)
+            .push("it's been an hour and you havn't")
+            .push_bold(format!("{}\n", v.schedule.task_secondary))
+            .push(format!("This makes puppy sad\n please {}", v.schedule.task,))
+            .build();
+
+        match self.messenger.send(v.guild, v.channel, message, None).await {
+            Ok(e) => {
+                tracing::info!(parent: &v.span, nag = e, "reminder nagged");
+                metrics::counter!(monitoring::NAGS_SENT).increment(1);
+                (v, Some(e))
+            }
+            Err(e) => {
+                tracing::warn!(
+                    parent: &v.span,
+                    error = %e,
+                    "cannot nag, puppy stops waiting on the reminder"
+                );
+                (v, None)
             }
         }
     }
-    async fn check_messages(
+
+    /// Records a reaction on a reminder or one of its nags and praises the user if
+    /// puppy was still waiting on it. Returns whether `message_id` belongs to an
+    /// occurrence puppy knows about.
+    pub async fn acknowledge(
+        messenger: &Arc<dyn Messenger>,
         active_messages: &Arc<Mutex<HashMap<u64, Message>>>,
-        message_map: &Arc<Mutex<HashMap<i64, u64>>>,
-        http: &serenity::http::Http,
-    ) {
+        channel_id: i64,
+        message_id: u64,
+        user_id: u64,
+        now: DateTime<Utc>,
+    ) -> bool {
+        Scheduler::record_acknowledgement(
+            messenger,
+            active_messages,
+            channel_id,
+            message_id,
+            user_id,
+            now,
+            true,
+        )
+        .await
+    }
+
+    /// Same as a reaction for a "done" reply or /done, except it can't be undone
+    /// by taking a reaction off
+    pub async fn mark_done(
+        messenger: &Arc<dyn Messenger>,
+        active_messages: &Arc<Mutex<HashMap<u64, Message>>>,
+        channel_id: i64,
+        message_id: u64,
+        user_id: u64,
+        now: DateTime<Utc>,
+    ) -> bool {
+        Scheduler::record_acknowledgement(
+            messenger,
+            active_messages,
+            channel_id,
+            message_id,
+            user_id,
+            now,
+            false,
+        )
+        .await
+    }
+
+    async fn record_acknowledgement(
+        messenger: &Arc<dyn Messenger>,
+        active_messages: &Arc<Mutex<HashMap<u64, Message>>>,
+        channel_id: i64,
+        message_id: u64,
+        user_id: u64,
+        now: DateTime<Utc>,
+        reaction: bool,
+    ) -> bool {
         let mut messages = active_messages.lock().await;
-        let mut messages_to_remove: Vec<u64> = Vec::new();
-        let mut messages_to_add: Vec<Message> = Vec::new();
-        let mut message_map_lock = message_map.lock().await;
-        let messages_itter = &mut *messages;
-
-        let now = Utc::now();
-
-        for (k, v) in messages_itter.iter() {
-            if (v.datetime + chrono::Duration::minutes(60)) < now {
-                let guild = serenity::GuildId::from(v.guild as u64);
-
-                let channel = match guild.channels(&http).await {
-                    Ok(e) => match e.get(&serenity::ChannelId::from(v.channel as u64)) {
-                        Some(c) => c.clone(),
-                        None => {
-                            println!("Cannot find channel {} in guild {}", v.channel, v.guild);
-                            continue;
+
+        let user = serenity::UserId::from(user_id);
+
+        let reponse = match occurrence(&mut messages, message_id) {
+            // Only the first acknowledgement gets praise
+            Some(e) if e.acknowledged.is_some() => {
+                match reaction {
+                    true => {
+                        e.reactions.insert((message_id, user_id));
+                    }
+                    false => {
+                        if let Some(ack) = e.acknowledged.as_mut() {
+                            ack.done = true;
                         }
-                    },
-                    Err(_) => {
-                        println!("Cannot fetch channels from guild {}", v.guild);
-                        continue;
                     }
                 };
+                return true;
+            }
+            Some(e) => {
+                if reaction {
+                    e.reactions.insert((message_id, user_id));
+                }
+                e.acknowledged = Some(Acknowledgement {
+                    at: now,
+                    praise: None,
+                    done: !reaction,
+                });
 
-                let message = match channel.message(http, v.message).await {
-                    Ok(e) => e,
-                    Err(e) => {
-                        println!(
-                            "Cannot get message {} from channel {} in guild {}",
-                            v.message.get(),
-                            v.channel,
-                            v.guild
-                        );
-                        println!("{}", e);
-                        continue;
-                    }
-                };
+                tracing::info!(parent: &e.span, by = user_id, "reminder acknowledged");
+                metrics::counter!(monitoring::ACKNOWLEDGEMENTS).increment(1);
 
-                messages_to_remove.push(k.clone());
+                serenity::MessageBuilder::new()
+                    .push("YAY ")
+                    .mention(&user)
+                    .push(format!(" you've {}!!\n", e.schedule.task_secondary))
+                    .push(format!(
+                        "You've been such a {} I'll give you {}!!!",
+                        e.schedule.praise_name, e.schedule.praise
+                    ))
+                    .build()
+            }
+            None => return false,
+        };
+
+        drop(messages);
 
-                if message.reactions.len() >= 1 {
-                    continue;
+        match messenger.reply(channel_id, message_id, reponse).await {
+            Ok(e) => {
+                let mut messages = active_messages.lock().await;
+
+                if let Some(ack) =
+                    occurrence(&mut messages, message_id).and_then(|m| m.acknowledged.as_mut())
+                {
+                    ack.praise = Some(e);
                 }
+            }
+            Err(e) => tracing::warn!(
+                error = %e,
+                channel = channel_id,
+                message = message_id,
+                "cannot reply to reaction"
+            ),
+        };
 
-                let user = serenity::UserId::from(v.schedule.user_id as u64);
+        true
+    }
 
-                let message = serenity::MessageBuilder::new()
-                    .mention(&user)
-                    .push("it's been an hour and you havn't")
-                    .push_bold(format!("{}\n", v.schedule.task_secondary))
-                    .push(format!("This makes puppy sad\n please {}", v.schedule.task,))
-                    .build();
-
-                match channel.say(&http, &message).await {
-                    Ok(e) => {
-                        let message_id: serenity::MessageId = e.into();
-
-                        message_map_lock.insert(v.schedule.id.clone(), message_id.get());
-
-                        messages_to_add.push(Message {
-                            message: message_id,
-                            datetime: Utc::now(),
-                            guild: v.guild.clone(),
-                            channel: v.channel.clone(),
-                            schedule: v.schedule.clone(),
-                        });
-                    }
-                    Err(e) => {
-                        println!(
-                            "Cannot send message to channel {} in guild {}",
-                            v.channel, v.guild
-                        );
-                        println!("{:?}", e);
-                        continue;
-                    }
+    /// Lets the user know puppy lost track of a reminder it sent, for reactions
+    /// and replies to reminders that aren't in `active_messages` anymore.
+    /// Reminders another instance sent and puppy's other messages are ignored.
+    pub async fn forgotten(
+        db: &Arc<dyn Storage>,
+        messenger: &Arc<dyn Messenger>,
+        instance: &str,
+        channel_id: i64,
+        message_id: u64,
+        user_id: u64,
+    ) {
+        match db.sent_by(&(message_id as i64), instance).await {
+            Ok(true) => (),
+            Ok(false) => return,
+            Err(e) => {
+                tracing::error!(error = %e, message = message_id, "cannot look up reminder");
+                return;
+            }
+        };
+
+        let response = serenity::MessageBuilder::new()
+            .push("Puppy can't rember this reminder anymore ")
+            .mention(&serenity::UserId::from(user_id))
+            .push("\n please react to the latest reminder so puppy can remember it")
+            .build();
+
+        if let Err(e) = messenger.reply(channel_id, message_id, response).await {
+            tracing::warn!(
+                error = %e,
+                channel = channel_id,
+                message = message_id,
+                "cannot reply to reaction"
+            );
+        }
+    }
+
+    /// Finds the oldest occurrence a user still hasn't done in a guild, optionally
+    /// only for one task. Returns the channel and reminder so it can be
+    /// acknowledged the same way as a reaction.
+    pub async fn oldest_unacknowledged(
+        active_messages: &Arc<Mutex<HashMap<u64, Message>>>,
+        guild_id: i64,
+        user_id: i64,
+        task_id: Option<i64>,
+    ) -> Option<(i64, u64)> {
+        active_messages
+            .lock()
+            .await
+            .values()
+            .filter(|v| {
+                v.acknowledged.is_none()
+                    && v.guild == guild_id
+                    && v.schedule.user_id == user_id
+                    && task_id.is_none_or(|id| v.schedule.id == id)
+            })
+            .min_by_key(|v| v.schedule.next_run)
+            .map(|v| (v.channel, v.message.get()))
+    }
+
+    /// Records a reaction being taken off a reminder. If it was the last one and
+    /// it was added moments ago the reminder goes back to waiting, the praise is
+    /// taken back and nags carry on. Returns whether that happened.
+    pub async fn remove_reaction(
+        messenger: &Arc<dyn Messenger>,
+        active_messages: &Arc<Mutex<HashMap<u64, Message>>>,
+        channel_id: i64,
+        message_id: u64,
+        user_id: u64,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let mut messages = active_messages.lock().await;
+
+        let e = match occurrence(&mut messages, message_id) {
+            Some(e) => e,
+            None => return false,
+        };
+
+        e.reactions.remove(&(message_id, user_id));
+
+        // Still done if someone else is reacting
+        if !e.reactions.is_empty() {
+            return false;
+        }
+
+        let praise = match &e.acknowledged {
+            Some(ack) if !ack.done && now - ack.at <= UNDO_GRACE => ack.praise,
+            _ => return false,
+        };
+
+        e.acknowledged = None;
+
+        tracing::info!(parent: &e.span, by = user_id, "reminder unacknowledged");
+
+        drop(messages);
+
+        if let Some(praise) = praise {
+            match messenger.delete(channel_id, praise).await {
+                Ok(_) => (),
+                Err(e) => tracing::warn!(
+                    error = %e,
+                    channel = channel_id,
+                    message = praise,
+                    "cannot take back praise"
+                ),
+            };
+        }
+
+        true
+    }
+
+    /// Pauses every task in a guild puppy was removed from. They are purged along
+    /// with the guild once the archive retention runs out, unless puppy is added back.
+    pub async fn guild_removed(db: &Arc<dyn Storage>, guild_id: i64, now: DateTime<Utc>) {
+        match db.set_guild_removed(&guild_id, Some(&now)).await {
+            Ok(_) => (),
+            Err(e) => tracing::error!(error = %e, guild = guild_id, "cannot mark guild as removed"),
+        };
+
+        match db
+            .pause_tasks(&guild_id, None, PAUSED_GUILD_REMOVED, &now)
+            .await
+        {
+            Ok(e) => tracing::info!(guild = guild_id, paused = e, "removed from guild"),
+            Err(e) => tracing::error!(error = %e, guild = guild_id, "cannot pause tasks"),
+        };
+    }
+
+    /// Brings back the tasks paused when puppy was removed from a guild.
+    /// Returns how many were resumed.
+    pub async fn guild_joined(db: &Arc<dyn Storage>, guild_id: i64, now: DateTime<Utc>) -> u64 {
+        match db.set_guild_removed(&guild_id, None).await {
+            Ok(_) => (),
+            Err(e) => tracing::error!(error = %e, guild = guild_id, "cannot mark guild as joined"),
+        };
+
+        match db
+            .resume_tasks(&guild_id, None, PAUSED_GUILD_REMOVED, &now)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => {
+                tracing::error!(error = %e, guild = guild_id, "cannot resume tasks");
+                0
+            }
+        }
+    }
+
+    /// Pauses the tasks of a member who left a guild so puppy stops pinging them.
+    /// They are purged once the archive retention runs out unless the member comes back.
+    pub async fn member_left(
+        db: &Arc<dyn Storage>,
+        guild_id: i64,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> u64 {
+        match db
+            .pause_tasks(&guild_id, Some(&user_id), PAUSED_MEMBER_LEFT, &now)
+            .await
+        {
+            Ok(e) => {
+                if e > 0 {
+                    tracing::info!(guild = guild_id, user = user_id, paused = e, "member left");
                 }
+                e
+            }
+            Err(e) => {
+                tracing::error!(error = %e, guild = guild_id, user = user_id, "cannot pause tasks");
+                0
             }
         }
+    }
 
-        for v in messages_to_add {
-            messages.insert(v.message.get(), v.clone());
+    /// Brings back the tasks paused when a member left, if they rejoin before they
+    /// are purged. Returns how many were resumed.
+    pub async fn member_joined(
+        db: &Arc<dyn Storage>,
+        guild_id: i64,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> u64 {
+        match db
+            .resume_tasks(&guild_id, Some(&user_id), PAUSED_MEMBER_LEFT, &now)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => {
+                tracing::error!(error = %e, guild = guild_id, user = user_id, "cannot resume tasks");
+                0
+            }
         }
+    }
 
-        for k in messages_to_remove {
-            messages.remove(&k);
+    /// Pauses the tasks of a guild whose reminder channel was deleted and lets the
+    /// guild owner know, /setchannel brings them back. Returns how many were paused.
+    pub async fn channel_deleted(
+        db: &Arc<dyn Storage>,
+        messenger: &Arc<dyn Messenger>,
+        guild_id: i64,
+        channel_id: i64,
+        channel_name: &str,
+        now: DateTime<Utc>,
+    ) -> u64 {
+        match db.get_guild(&guild_id).await {
+            Ok(Some(e)) if e.channel == channel_id => (),
+            Ok(_) => return 0,
+            Err(e) => {
+                tracing::error!(error = %e, guild = guild_id, "cannot fetch guild");
+                return 0;
+            }
+        };
+
+        let paused = match db
+            .pause_tasks(&guild_id, None, PAUSED_CHANNEL_DELETED, &now)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => {
+                tracing::error!(error = %e, guild = guild_id, "cannot pause tasks");
+                return 0;
+            }
+        };
+
+        if paused == 0 {
+            return 0;
         }
+
+        let message = serenity::MessageBuilder::new()
+            .push("Puppy's home ")
+            .push_bold(format!("#{}", channel_name))
+            .push(format!(
+                " was deleted so puppy has paused {} reminders\n",
+                paused
+            ))
+            .push("Use /setchannel in a new channel and puppy will start them again")
+            .build();
+
+        match messenger.message_owner(guild_id, message).await {
+            Ok(_) => (),
+            Err(e) => tracing::warn!(
+                error = %e,
+                guild = guild_id,
+                channel = channel_id,
+                "cannot tell the guild owner about the channel"
+            ),
+        };
+
+        paused
     }
 }
 
+/// Whether a reply to a reminder is saying it has been done
+fn is_done_reply(content: &str) -> bool {
+    content
+        .trim()
+        .trim_end_matches(['!', '.'])
+        .eq_ignore_ascii_case("done")
+}
+
 pub async fn event_handler(
-    ctx: &serenity::Context,
+    _ctx: &serenity::Context,
     event: &serenity::FullEvent,
-    _framework: poise::FrameworkContext<'_, Data, Error>,
+    framework: poise::FrameworkContext<'_, Data, Error>,
     data: &Data,
 ) -> Result<(), Error> {
-    println!(
-        "Got an event in event handler: {:?}",
-        event.snake_case_name()
-    );
+    tracing::trace!(event = event.snake_case_name(), "gateway event");
 
     match event {
         serenity::FullEvent::ReactionAdd { add_reaction } => {
-            if add_reaction.message_author_id.unwrap()
-                != ctx
-                    .http
-                    .get_current_application_info()
-                    .await
-                    .expect("Can't get current appilacion info")
-                    .id
-                    .get()
-            {
+            // Only reactions to puppy's own messages count
+            match add_reaction.message_author_id {
+                Some(e) if e == framework.bot_id => (),
+                _ => return Ok(()),
+            };
+
+            let user_id = match add_reaction.user_id {
+                Some(e) => e.get(),
+                None => return Ok(()),
+            };
+
+            let found = Scheduler::acknowledge(
+                &data.messenger,
+                &data.active_messages,
+                add_reaction.channel_id.get() as i64,
+                add_reaction.message_id.get(),
+                user_id,
+                data.clock.now(),
+            )
+            .await;
+
+            if !found {
+                Scheduler::forgotten(
+                    &data.db,
+                    &data.messenger,
+                    &data.instance,
+                    add_reaction.channel_id.get() as i64,
+                    add_reaction.message_id.get(),
+                    user_id,
+                )
+                .await;
+            }
+        }
+        serenity::FullEvent::ReactionRemove { removed_reaction } => {
+            let user_id = match removed_reaction.user_id {
+                Some(e) => e.get(),
+                None => return Ok(()),
+            };
+
+            Scheduler::remove_reaction(
+                &data.messenger,
+                &data.active_messages,
+                removed_reaction.channel_id.get() as i64,
+                removed_reaction.message_id.get(),
+                user_id,
+                data.clock.now(),
+            )
+            .await;
+        }
+        serenity::FullEvent::Message { new_message } => {
+            if new_message.author.bot || !is_done_reply(&new_message.content) {
                 return Ok(());
             }
 
-            let mut messages = data.active_messages.lock().await;
+            // Only replies to puppy's own reminders count
+            let reminder = match &new_message.referenced_message {
+                Some(e) if e.author.id == framework.bot_id => e.id.get(),
+                _ => return Ok(()),
+            };
 
-            let m = messages.get(&add_reaction.message_id.get());
+            // Puppy stays quiet about replies to anything it isn't waiting on,
+            // "done" is a normal thing to say to its other messages
+            Scheduler::mark_done(
+                &data.messenger,
+                &data.active_messages,
+                new_message.channel_id.get() as i64,
+                reminder,
+                new_message.author.id.get(),
+                data.clock.now(),
+            )
+            .await;
+        }
+        serenity::FullEvent::ChannelDelete { channel, .. } => {
+            data.channels.remove(channel.id.get() as i64);
 
-            match m {
-                Some(e) => {
-                    let reponse = serenity::MessageBuilder::new()
-                        .push("YAY ")
-                        .mention(&add_reaction.user_id.unwrap())
-                        .push(format!(" you've {}!!\n", e.schedule.task_secondary))
-                        .push(format!(
-                            "You've been such a {} I'll give you {}!!!",
-                            e.schedule.praise_name, e.schedule.praise
-                        ))
-                        .build();
+            Scheduler::channel_deleted(
+                &data.db,
+                &data.messenger,
+                channel.guild_id.get() as i64,
+                channel.id.get() as i64,
+                &channel.name,
+                data.clock.now(),
+            )
+            .await;
+        }
+        serenity::FullEvent::GuildDelete { incomplete, .. } => {
+            // Unavailable means a Discord outage rather than puppy being removed
+            if incomplete.unavailable {
+                return Ok(());
+            }
 
-                    match add_reaction
-                        .message(&ctx.http)
-                        .await
-                        .unwrap()
-                        .reply(&ctx.http, reponse)
-                        .await
-                    {
-                        Ok(_) => (),
-                        Err(e) => {
-                            println!(
-                                "Cannot reply to message {} from channel {} in guild {}",
-                                add_reaction.message_id.get(),
-                                add_reaction.channel_id.get(),
-                                match add_reaction.guild_id {
-                                    Some(e) => e.get().to_string(),
-                                    None => "NOID".to_string(),
-                                }
-                            );
-                            println!("{}", e);
-                        }
-                    };
-                    messages.remove(&add_reaction.message_id.get());
-                }
-                None => {
-                    let reponse = serenity::MessageBuilder::new()
-                        .push("Puppy's memory can only rember the latest reminder")
-                        .mention(&add_reaction.user_id.unwrap())
-                        .push("\n please react to the latest reminder so puppy can remember it")
-                        .build();
-
-                    match add_reaction
-                        .message(&ctx.http)
-                        .await
-                        .unwrap()
-                        .reply(&ctx.http, reponse)
-                        .await
-                    {
-                        Ok(_) => (),
-                        Err(e) => {
-                            println!(
-                                "Cannot reply to message {} from channel {} in guild {}",
-                                add_reaction.message_id.get(),
-                                add_reaction.channel_id.get(),
-                                match add_reaction.guild_id {
-                                    Some(e) => e.get().to_string(),
-                                    None => "NOID".to_string(),
-                                }
-                            );
-                            println!("{}", e);
-                        }
-                    };
-                }
+            Scheduler::guild_removed(&data.db, incomplete.id.get() as i64, data.clock.now()).await;
+        }
+        serenity::FullEvent::GuildMemberRemoval { guild_id, user, .. } => {
+            Scheduler::member_left(
+                &data.db,
+                guild_id.get() as i64,
+                user.id.get() as i64,
+                data.clock.now(),
+            )
+            .await;
+        }
+        serenity::FullEvent::GuildMemberAddition { new_member } => {
+            let resumed = Scheduler::member_joined(
+                &data.db,
+                new_member.guild_id.get() as i64,
+                new_member.user.id.get() as i64,
+                data.clock.now(),
+            )
+            .await;
+
+            if resumed > 0 {
+                data.wakeup.notify_one();
+            }
+        }
+        serenity::FullEvent::GuildCreate { guild, .. } => {
+            let resumed =
+                Scheduler::guild_joined(&data.db, guild.id.get() as i64, data.clock.now()).await;
+
+            if resumed > 0 {
+                data.wakeup.notify_one();
             }
         }
+        serenity::FullEvent::ChannelUpdate { new, .. } => {
+            data.channels.remove(new.id.get() as i64);
+        }
         _ => {}
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::ManualClock;
+    use crate::messenger::FakeMessenger;
+    use crate::repo::memory::MemoryStorage;
+    use crate::repo::testing::{self, at, claim, interval, task};
+
+    async fn setup(clock: &ManualClock) -> (MemoryStorage, User) {
+        let (db, user) = testing::setup(MemoryStorage::new(), chrono::TimeDelta::hours(10)).await;
+
+        db.add_task(Task {
+            interval: interval(chrono::TimeDelta::hours(12)),
+            created: clock.now(),
+            ..task(&user, clock.now() + chrono::TimeDelta::minutes(30))
+        })
+        .await
+        .unwrap();
+
+        (db, user)
+    }
+
+    #[tokio::test]
+    async fn schedule_is_due_once_clock_passes_next_run() {
+        let clock = ManualClock::new(at("2025-01-01T08:00:00Z"));
+        let (db, _) = setup(&clock).await;
+
+        assert_eq!(db.enqueue_due(&clock.now()).await.unwrap(), 0);
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+
+        assert_eq!(db.enqueue_due(&clock.now()).await.unwrap(), 1);
+
+        // Queueing moves the task on, so it is not due again straight away
+        assert_eq!(db.enqueue_due(&clock.now()).await.unwrap(), 0);
+
+        clock.set(at("2025-01-01T20:31:00Z"));
+        assert_eq!(db.enqueue_due(&clock.now()).await.unwrap(), 1);
+
+        let due = claim(&db, &clock.now(), "a").await;
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[1].schedule.next_run, at("2025-01-01T20:30:00Z"));
+    }
+
+    #[tokio::test]
+    async fn nag_waits_an_hour() {
+        let clock = ManualClock::new(at("2025-01-01T08:00:00Z"));
+        let (db, _) = setup(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+
+        db.enqueue_due(&clock.now()).await.unwrap();
+        let schedule = claim(&db, &clock.now(), "a").await[0].schedule.clone();
+
+        let message = Message {
+            message: serenity::MessageId::new(1),
+            nags: Vec::new(),
+            datetime: clock.now(),
+            guild: schedule.guild_id,
+            channel: schedule.channel_id,
+            schedule,
+            reactions: HashSet::new(),
+            acknowledged: None,
+            span: tracing::Span::none(),
+        };
+
+        clock.advance(chrono::TimeDelta::minutes(59));
+        assert!(!Scheduler::needs_nag(&message, clock.now()));
+
+        clock.advance(chrono::TimeDelta::minutes(1));
+        assert!(!Scheduler::needs_nag(&message, clock.now()));
+
+        clock.advance(chrono::TimeDelta::seconds(1));
+        assert!(Scheduler::needs_nag(&message, clock.now()));
+    }
+
+    async fn scheduler(
+        clock: &Arc<ManualClock>,
+    ) -> (Scheduler, Arc<MemoryStorage>, Arc<FakeMessenger>) {
+        let (db, _) = setup(clock).await;
+        let db = Arc::new(db);
+        let messenger = Arc::new(FakeMessenger::new());
+
+        let scheduler = Scheduler::new(
+            db.clone(),
+            messenger.clone(),
+            clock.clone(),
+            Arc::new(Mutex::new(HashMap::new())),
+            ":tailwag:".to_owned(),
+            "test".to_owned(),
+            Arc::new(Notify::new()),
+        );
+
+        (scheduler, db, messenger)
+    }
+
+    #[tokio::test]
+    async fn reminder_nag_ack() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, messenger) = scheduler(&clock).await;
+
+        scheduler.process_schedule().await;
+        assert!(messenger.sent().is_empty());
+
+        // The reminder goes out once it is due and the task moves on
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+
+        let reminder = messenger.last();
+        assert_eq!(reminder.channel_id, 200);
+        assert!(reminder.content.contains("<@300>"));
+        assert!(reminder.content.contains("take meds"));
+        assert_eq!(
+            db.get_task_user(&100, &300).await.unwrap()[0].next_run,
+            at("2025-01-01T20:30:00Z")
+        );
+
+        // No nag until an hour has passed
+        clock.advance(chrono::TimeDelta::minutes(30));
+        scheduler.check_messages().await;
+        assert_eq!(messenger.sent().len(), 1);
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.check_messages().await;
+
+        let nag = messenger.last();
+        assert_eq!(messenger.sent().len(), 2);
+        assert!(nag.content.contains("it's been an hour"));
+
+        // Reacting to the nag gets praise and stops any more nags
+        assert!(
+            Scheduler::acknowledge(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                nag.id,
+                300,
+                clock.now()
+            )
+            .await
+        );
+
+        let praise = messenger.last();
+        assert_eq!(praise.reply_to, Some(nag.id));
+        assert!(praise.content.contains("good pup"));
+        assert!(praise.content.contains("pats"));
+
+        clock.advance(chrono::TimeDelta::hours(2));
+        scheduler.check_messages().await;
+        assert_eq!(messenger.sent().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn reminder_is_acknowledged_after_nags() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        let reminder = messenger.last();
+
+        for _ in 0..2 {
+            clock.advance(chrono::TimeDelta::minutes(61));
+            scheduler.check_messages().await;
+        }
+        assert_eq!(messenger.sent().len(), 3);
+
+        // The original reminder still counts once it has been nagged about
+        assert!(
+            Scheduler::acknowledge(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                reminder.id,
+                300,
+                clock.now()
+            )
+            .await
+        );
+        assert_eq!(messenger.last().reply_to, Some(reminder.id));
+        assert!(messenger.last().content.contains("good pup"));
+
+        clock.advance(chrono::TimeDelta::hours(2));
+        scheduler.check_messages().await;
+        assert_eq!(messenger.sent().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn oldest_unacknowledged_reminder_is_found() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        let first = messenger.last();
+
+        let mut task = db
+            .get_task_id(&db.get_task_user(&100, &300).await.unwrap()[0].id)
+            .await
+            .unwrap()
+            .unwrap();
+        task.task = "drink water".to_owned();
+        task.next_run = clock.now();
+        let second = db.add_task(task).await.unwrap();
+
+        clock.advance(chrono::TimeDelta::minutes(1));
+        scheduler.process_schedule().await;
+
+        let active = &scheduler.active_messages;
+        assert_eq!(
+            Scheduler::oldest_unacknowledged(active, 100, 300, None).await,
+            Some((200, first.id))
+        );
+        assert_eq!(
+            Scheduler::oldest_unacknowledged(active, 100, 300, Some(second.id)).await,
+            Some((200, messenger.last().id))
+        );
+        assert_eq!(
+            Scheduler::oldest_unacknowledged(active, 100, 301, None).await,
+            None
+        );
+
+        Scheduler::acknowledge(
+            &scheduler.messenger,
+            active,
+            200,
+            first.id,
+            300,
+            clock.now(),
+        )
+        .await;
+        assert_ne!(
+            Scheduler::oldest_unacknowledged(active, 100, 300, None).await,
+            Some((200, first.id))
+        );
+    }
+
+    #[test]
+    fn done_reply_is_recognised() {
+        assert!(is_done_reply("done"));
+        assert!(is_done_reply(" Done!! "));
+        assert!(!is_done_reply("not done"));
+        assert!(!is_done_reply("done soon"));
+    }
+
+    #[tokio::test]
+    async fn reaction_stops_nag() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        let reminder = messenger.last();
+
+        assert!(
+            Scheduler::acknowledge(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                reminder.id,
+                300,
+                clock.now()
+            )
+            .await
+        );
+
+        clock.advance(chrono::TimeDelta::minutes(61));
+        scheduler.check_messages().await;
+
+        // Just the reminder and the praise
+        assert_eq!(messenger.sent().len(), 2);
+        assert!(scheduler.active_messages.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reactions_are_tracked_and_praised_once() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        let reminder = messenger.last();
+
+        for user in [300, 301] {
+            Scheduler::acknowledge(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                reminder.id,
+                user,
+                clock.now(),
+            )
+            .await;
+        }
+        assert_eq!(messenger.sent().len(), 2);
+
+        Scheduler::remove_reaction(
+            &scheduler.messenger,
+            &scheduler.active_messages,
+            200,
+            reminder.id,
+            301,
+            clock.now(),
+        )
+        .await;
+
+        let active = scheduler.active_messages.lock().await;
+        let state = active.get(&reminder.id).unwrap();
+        assert!(state.acknowledged.is_some());
+        assert_eq!(state.reactions, HashSet::from([(reminder.id, 300)]));
+    }
+
+    #[tokio::test]
+    async fn removed_reaction_undoes_acknowledgement() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        let reminder = messenger.last();
+
+        Scheduler::acknowledge(
+            &scheduler.messenger,
+            &scheduler.active_messages,
+            200,
+            reminder.id,
+            300,
+            clock.now(),
+        )
+        .await;
+        let praise = messenger.last();
+
+        clock.advance(chrono::TimeDelta::minutes(2));
+        assert!(
+            Scheduler::remove_reaction(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                reminder.id,
+                300,
+                clock.now(),
+            )
+            .await
+        );
+        assert_eq!(messenger.deleted(), vec![praise.id]);
+
+        // Nags carry on as if nobody had reacted
+        clock.advance(chrono::TimeDelta::minutes(59));
+        scheduler.check_messages().await;
+        assert_eq!(messenger.sent().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn removed_reaction_keeps_done_reply() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        let reminder = messenger.last();
+
+        Scheduler::acknowledge(
+            &scheduler.messenger,
+            &scheduler.active_messages,
+            200,
+            reminder.id,
+            300,
+            clock.now(),
+        )
+        .await;
+
+        Scheduler::mark_done(
+            &scheduler.messenger,
+            &scheduler.active_messages,
+            200,
+            reminder.id,
+            300,
+            clock.now(),
+        )
+        .await;
+
+        clock.advance(chrono::TimeDelta::minutes(2));
+        assert!(
+            !Scheduler::remove_reaction(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                reminder.id,
+                300,
+                clock.now(),
+            )
+            .await
+        );
+        assert!(messenger.deleted().is_empty());
+
+        // Still done, so no nag
+        clock.advance(chrono::TimeDelta::minutes(59));
+        scheduler.check_messages().await;
+        assert_eq!(messenger.sent().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn done_reply_to_other_message_is_ignored() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        let reminder = messenger.last();
+
+        Scheduler::acknowledge(
+            &scheduler.messenger,
+            &scheduler.active_messages,
+            200,
+            reminder.id,
+            300,
+            clock.now(),
+        )
+        .await;
+        let praise = messenger.last();
+        assert_ne!(praise.id, reminder.id);
+
+        assert!(
+            !Scheduler::mark_done(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                praise.id,
+                300,
+                clock.now(),
+            )
+            .await
+        );
+        assert_eq!(messenger.sent().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn late_removed_reaction_keeps_acknowledgement() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        let reminder = messenger.last();
+
+        Scheduler::acknowledge(
+            &scheduler.messenger,
+            &scheduler.active_messages,
+            200,
+            reminder.id,
+            300,
+            clock.now(),
+        )
+        .await;
+
+        clock.advance(UNDO_GRACE + chrono::TimeDelta::minutes(1));
+        assert!(
+            !Scheduler::remove_reaction(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                reminder.id,
+                300,
+                clock.now(),
+            )
+            .await
+        );
+        assert!(messenger.deleted().is_empty());
+
+        clock.advance(chrono::TimeDelta::minutes(60));
+        scheduler.check_messages().await;
+        assert_eq!(messenger.sent().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stale_reminder_is_not_acknowledged() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        let reminder = messenger.last();
+
+        // The next reminder takes over from this one
+        clock.set(at("2025-01-01T20:31:00Z"));
+        scheduler.process_schedule().await;
+
+        assert!(
+            !Scheduler::acknowledge(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                reminder.id,
+                300,
+                clock.now()
+            )
+            .await
+        );
+        assert_eq!(messenger.sent().len(), 2);
+
+        // Only the instance that sent it owns up to forgetting it
+        for instance in ["other", "test"] {
+            Scheduler::forgotten(
+                &scheduler.db,
+                &scheduler.messenger,
+                instance,
+                200,
+                reminder.id,
+                300,
+            )
+            .await;
+        }
+        assert_eq!(messenger.sent().len(), 3);
+        assert!(messenger.last().content.contains("latest reminder"));
+    }
+
+    #[tokio::test]
+    async fn reaction_to_other_message_is_ignored() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+
+        // Praise and command responses are puppy's messages too
+        let praise = 999_999;
+        assert!(
+            !Scheduler::acknowledge(
+                &scheduler.messenger,
+                &scheduler.active_messages,
+                200,
+                praise,
+                300,
+                clock.now()
+            )
+            .await
+        );
+        Scheduler::forgotten(
+            &scheduler.db,
+            &scheduler.messenger,
+            "test",
+            200,
+            praise,
+            300,
+        )
+        .await;
+        assert_eq!(messenger.sent().len(), 1);
+    }
+    #[tokio::test]
+    async fn failed_send_is_retried() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, messenger) = scheduler(&clock).await;
+
+        messenger.fail_sends(1);
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        assert!(messenger.sent().is_empty());
+
+        // The task still moved on, the reminder waits in the outbox instead
+        assert_eq!(
+            db.get_task_user(&100, &300).await.unwrap()[0].next_run,
+            at("2025-01-01T20:30:00Z")
+        );
+
+        clock.advance(RETRY_DELAY);
+        scheduler.process_schedule().await;
+
+        assert_eq!(messenger.sent().len(), 1);
+        assert!(messenger.last().content.contains("take meds"));
+
+        clock.advance(RETRY_DELAY);
+        scheduler.process_schedule().await;
+        assert_eq!(messenger.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn delivery_taken_over_after_crash_is_not_sent_twice() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, messenger) = scheduler(&clock).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        db.enqueue_due(&clock.now()).await.unwrap();
+
+        // Another instance sends the reminder then dies before marking it sent
+        let delivery = claim(db.as_ref(), &clock.now(), "crashed").await[0].clone();
+        messenger
+            .send(
+                100,
+                200,
+                "reminder".to_owned(),
+                Some(format!("d{}", delivery.id)),
+            )
+            .await
+            .unwrap();
+
+        scheduler.process_schedule().await;
+        assert_eq!(messenger.sent().len(), 1);
+
+        clock.advance(LEASE + chrono::TimeDelta::seconds(1));
+        scheduler.process_schedule().await;
+
+        assert_eq!(messenger.sent().len(), 1);
+        assert!(claim(db.as_ref(), &clock.now(), "other").await.is_empty());
+    }
+    #[tokio::test]
+    async fn sleeps_until_next_run() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, _) = scheduler(&clock).await;
+
+        // The run is 30 minutes away but it still checks in every so often
+        assert_eq!(
+            scheduler.time_until_next_run().await,
+            MAX_SLEEP.to_std().unwrap()
+        );
+
+        clock.advance(chrono::TimeDelta::minutes(25));
+        assert_eq!(
+            scheduler.time_until_next_run().await,
+            Duration::from_secs(5 * 60)
+        );
+
+        // Even with nothing left to run
+        let task = db.get_task_user(&100, &300).await.unwrap()[0].clone();
+        db.delete_task(&task.id).await.unwrap();
+        assert_eq!(
+            scheduler.time_until_next_run().await,
+            MAX_SLEEP.to_std().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_wakes_for_retry() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        messenger.fail_sends(1);
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+
+        assert_eq!(
+            scheduler.time_until_next_run().await,
+            RETRY_DELAY.to_std().unwrap()
+        );
+    }
+    #[test]
+    fn retry_delay_doubles_up_to_an_hour() {
+        assert_eq!(Scheduler::retry_delay(0), chrono::TimeDelta::minutes(1));
+        assert_eq!(Scheduler::retry_delay(3), chrono::TimeDelta::minutes(8));
+        assert_eq!(Scheduler::retry_delay(10), MAX_RETRY_DELAY);
+        assert_eq!(Scheduler::retry_delay(40), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn delivery_is_given_up_after_max_attempts() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, messenger) = scheduler(&clock).await;
+
+        messenger.fail_sends(MAX_ATTEMPTS as usize);
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        for attempts in 0..MAX_ATTEMPTS {
+            scheduler.process_schedule().await;
+            clock.advance(Scheduler::retry_delay(attempts));
+        }
+
+        assert!(messenger.sent().is_empty());
+        assert!(claim(db.as_ref(), &clock.now(), "other").await.is_empty());
+
+        let owner_messages = messenger.owner_messages();
+        assert_eq!(owner_messages.len(), 1);
+        assert_eq!(owner_messages[0].0, 100);
+        assert!(owner_messages[0].1.contains("<#200>"));
+    }
+
+    #[tokio::test]
+    async fn missing_channel_is_reported_once() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, _, messenger) = scheduler(&clock).await;
+
+        messenger.remove_channel(200);
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+
+        // Not worth retrying a channel that is gone
+        assert_eq!(messenger.owner_messages().len(), 1);
+        assert_eq!(
+            scheduler.time_until_next_run().await,
+            MAX_SLEEP.to_std().unwrap()
+        );
+
+        clock.advance(chrono::TimeDelta::hours(12));
+        scheduler.process_schedule().await;
+
+        assert!(messenger.sent().is_empty());
+        assert_eq!(messenger.owner_messages().len(), 1);
+    }
+    #[tokio::test]
+    async fn deleted_channel_pauses_tasks_and_tells_owner() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, messenger) = scheduler(&clock).await;
+        let storage: Arc<dyn Storage> = db.clone();
+
+        // Some other channel going away doesn't matter
+        assert_eq!(
+            Scheduler::channel_deleted(
+                &storage,
+                &scheduler.messenger,
+                100,
+                201,
+                "general",
+                clock.now()
+            )
+            .await,
+            0
+        );
+        assert!(messenger.owner_messages().is_empty());
+
+        assert_eq!(
+            Scheduler::channel_deleted(
+                &storage,
+                &scheduler.messenger,
+                100,
+                200,
+                "reminders",
+                clock.now()
+            )
+            .await,
+            1
+        );
+        assert!(messenger.owner_messages()[0].1.contains("/setchannel"));
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        assert!(messenger.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tasks_come_back_when_puppy_rejoins() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, messenger) = scheduler(&clock).await;
+        let storage: Arc<dyn Storage> = db.clone();
+
+        Scheduler::guild_removed(&storage, 100, clock.now()).await;
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        assert!(messenger.sent().is_empty());
+
+        assert_eq!(Scheduler::guild_joined(&storage, 100, clock.now()).await, 1);
+
+        // Missed runs are skipped rather than sent late
+        scheduler.process_schedule().await;
+        assert!(messenger.sent().is_empty());
+
+        clock.set(at("2025-01-01T20:31:00Z"));
+        scheduler.process_schedule().await;
+        assert_eq!(messenger.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn member_tasks_pause_until_they_rejoin() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, messenger) = scheduler(&clock).await;
+        let storage: Arc<dyn Storage> = db.clone();
+
+        assert_eq!(
+            Scheduler::member_left(&storage, 100, 301, clock.now()).await,
+            0
+        );
+        assert_eq!(
+            Scheduler::member_left(&storage, 100, 300, clock.now()).await,
+            1
+        );
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        assert!(messenger.sent().is_empty());
+
+        assert_eq!(
+            Scheduler::member_joined(&storage, 100, 300, clock.now()).await,
+            1
+        );
+
+        clock.set(at("2025-01-01T20:31:00Z"));
+        scheduler.process_schedule().await;
+        assert_eq!(messenger.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn every_unanswered_reminder_is_nagged() {
+        let clock = Arc::new(ManualClock::new(at("2025-01-01T08:00:00Z")));
+        let (scheduler, db, messenger) = scheduler(&clock).await;
+
+        let mut task = db
+            .get_task_id(&db.get_task_user(&100, &300).await.unwrap()[0].id)
+            .await
+            .unwrap()
+            .unwrap();
+        task.task = "drink water".to_owned();
+        db.add_task(task).await.unwrap();
+
+        clock.advance(chrono::TimeDelta::minutes(31));
+        scheduler.process_schedule().await;
+        assert_eq!(messenger.sent().len(), 2);
+
+        clock.advance(chrono::TimeDelta::minutes(61));
+        scheduler.check_messages().await;
+
+        let nags: Vec<u64> = messenger.sent()[2..].iter().map(|m| m.id).collect();
+        assert_eq!(nags.len(), 2);
+
+        let active = scheduler.active_messages.lock().await;
+        assert_eq!(active.len(), 2);
+        assert!(nags
+            .iter()
+            .all(|id| active.values().any(|v| v.nags == vec![*id])));
+    }
+}
@@ -0,0 +1,1141 @@
+use std::str::FromStr;
+
+use chrono::TimeDelta;
+use sqlx::postgres::types::PgInterval;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow};
+use sqlx::types::chrono::{DateTime, NaiveTime, Utc};
+use sqlx::{Pool, Row, Sqlite};
+
+use async_trait::async_trait;
+
+use crate::repo::storage::*;
+
+/// SQLite implementation of `Storage` for single server deployments.
+/// Intervals are stored as microseconds, times of day as comma separated text
+/// and timestamps as RFC 3339 text in UTC.
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    db: Pool<Sqlite>,
+}
+
+const TASK_COLUMNS: &str = "s.id, s.guildid, s.userid, s.task, s.tasksecondary, s.interval, s.times, s.created, s.nextrun, s.enddate, s.maxruns, s.runs";
+
+fn to_interval(microseconds: i64) -> PgInterval {
+    PgInterval {
+        months: 0,
+        days: 0,
+        microseconds,
+    }
+}
+
+fn times_to_text(times: &[NaiveTime]) -> String {
+    times
+        .iter()
+        .map(|t| t.format("%H:%M:%S").to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn text_to_times(text: &str) -> Vec<NaiveTime> {
+    text.split(',')
+        .filter_map(|t| NaiveTime::parse_from_str(t, "%H:%M:%S").ok())
+        .collect()
+}
+
+fn task_from_row(e: &SqliteRow) -> Result<Task, sqlx::Error> {
+    Ok(Task {
+        id: e.try_get("id")?,
+        guild_id: e.try_get("guildid")?,
+        user_id: e.try_get("userid")?,
+        task: e.try_get("task")?,
+        task_secondary: e.try_get("tasksecondary")?,
+        interval: to_interval(e.try_get("interval")?),
+        times: text_to_times(e.try_get("times")?),
+        created: e.try_get("created")?,
+        next_run: e.try_get("nextrun")?,
+        end_date: e.try_get("enddate")?,
+        max_runs: e.try_get("maxruns")?,
+        runs: e.try_get("runs")?,
+    })
+}
+
+/// Needs `TASK_COLUMNS` plus the guild channel and the owner's details
+fn schedule_from_row(e: &SqliteRow) -> Result<Schedule, sqlx::Error> {
+    let t = task_from_row(e)?;
+
+    Ok(Schedule {
+        id: t.id,
+        guild_id: t.guild_id,
+        user_id: e.try_get("discordid")?,
+        channel_id: e.try_get("channel")?,
+        task: t.task,
+        task_secondary: t.task_secondary,
+        praise: e.try_get("praise")?,
+        praise_name: e.try_get("praisename")?,
+        interval: t.interval,
+        times: t.times,
+        timezone: to_interval(e.try_get("timezone")?),
+        created: t.created,
+        next_run: t.next_run,
+        end_date: t.end_date,
+        max_runs: t.max_runs,
+        runs: t.runs,
+    })
+}
+
+/// Needs the columns for `schedule_from_row` plus the delivery's id, run, runat and attempts
+fn delivery_from_row(e: &SqliteRow) -> Result<Delivery, sqlx::Error> {
+    let schedule = schedule_from_row(e)?;
+
+    Ok(Delivery {
+        id: e.try_get("deliveryid")?,
+        attempts: e.try_get("attempts")?,
+        schedule: Schedule {
+            next_run: e.try_get("runat")?,
+            runs: e.try_get("run")?,
+            ..schedule
+        },
+    })
+}
+
+fn user_from_row(e: &SqliteRow) -> Result<User, sqlx::Error> {
+    Ok(User {
+        id: e.try_get("id")?,
+        guild_id: e.try_get("guildid")?,
+        user_id: e.try_get("userid")?,
+        praise: e.try_get("praise")?,
+        praise_name: e.try_get("praisename")?,
+        timezone: to_interval(e.try_get("timezone")?),
+    })
+}
+
+fn archived_from_row(e: &SqliteRow) -> Result<ArchivedTask, sqlx::Error> {
+    Ok(ArchivedTask {
+        id: e.try_get("id")?,
+        guild_id: e.try_get("guildid")?,
+        user_id: e.try_get("userid")?,
+        task: e.try_get("task")?,
+        end_date: e.try_get("enddate")?,
+        archived: e.try_get("archived")?,
+        reason: e
+            .try_get::<Option<String>, _>("archivereason")?
+            .unwrap_or_default(),
+        timezone: to_interval(e.try_get("timezone")?),
+    })
+}
+
+impl SqliteDatabase {
+    pub async fn new(database_url: String) -> Result<SqliteDatabase, DatabaseErrors> {
+        let options = match SqliteConnectOptions::from_str(&database_url) {
+            Ok(e) => e.create_if_missing(true).foreign_keys(true),
+            Err(_) => return Err(DatabaseErrors::CannotConect),
+        };
+
+        let sql_pool = match SqlitePoolOptions::new().connect_with(options).await {
+            Ok(e) => e,
+            Err(_) => return Err(DatabaseErrors::CannotConect),
+        };
+
+        let migrator = sqlx::migrate!("./migrations_sqlite");
+
+        match migrator.run(&sql_pool).await {
+            Ok(_) => Ok(SqliteDatabase { db: sql_pool }),
+            Err(e) => Err(DatabaseErrors::MigrationError(e)),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteDatabase {
+    async fn get_guild(&self, guild: &i64) -> Result<Option<Guild>, DatabaseErrors> {
+        let opt = match sqlx::query("SELECT guildid, channel FROM guilds WHERE guildid = ?1")
+            .bind(guild)
+            .fetch_optional(&self.db)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match opt {
+            Some(e) => Ok(Some(Guild {
+                id: e.get("guildid"),
+                channel: e.get("channel"),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn update_guild(&self, guild: &Guild) -> Result<Guild, DatabaseErrors> {
+        match sqlx::query(
+            "INSERT INTO guilds (guildid, channel) VALUES (?1, ?2) ON CONFLICT (guildid) DO UPDATE SET channel = excluded.channel",
+        )
+        .bind(guild.id)
+        .bind(guild.channel)
+        .execute(&self.db)
+        .await
+        {
+            Ok(_) => Ok(guild.clone()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn set_guild_removed(
+        &self,
+        guild_id: &i64,
+        removed: Option<&DateTime<Utc>>,
+    ) -> Result<(), DatabaseErrors> {
+        match sqlx::query("UPDATE guilds SET removed = ?2 WHERE guildid = ?1")
+            .bind(guild_id)
+            .bind(removed)
+            .execute(&self.db)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn get_task_id(&self, id: &i64) -> Result<Option<Task>, DatabaseErrors> {
+        let opt = match sqlx::query(&format!(
+            "SELECT {} FROM schedule s INNER JOIN users u ON s.userid = u.id AND s.id = ?1 AND s.archived IS NULL",
+            TASK_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match opt {
+            Some(e) => match task_from_row(&e) {
+                Ok(t) => Ok(Some(t)),
+                Err(e) => Err(DatabaseErrors::Query(e)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn get_task_user(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+    ) -> Result<Vec<UserTask>, DatabaseErrors> {
+        let rows = match sqlx::query(&format!(
+            "SELECT {}, u.userid AS discordid, u.timezone FROM schedule s INNER JOIN users u ON s.userid = u.id AND u.guildid = ?1 AND u.userid = ?2 WHERE s.archived IS NULL",
+            TASK_COLUMNS
+        ))
+        .bind(guild_id)
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let mut res: Vec<UserTask> = Vec::new();
+
+        for e in rows {
+            let t = match task_from_row(&e) {
+                Ok(t) => t,
+                Err(e) => return Err(DatabaseErrors::Query(e)),
+            };
+
+            res.push(UserTask {
+                id: t.id,
+                guild_id: t.guild_id,
+                user_id: e.get("discordid"),
+                task: t.task,
+                task_secondary: t.task_secondary,
+                interval: t.interval,
+                times: t.times,
+                created: t.created,
+                next_run: t.next_run,
+                end_date: t.end_date,
+                max_runs: t.max_runs,
+                runs: t.runs,
+                timezone: to_interval(e.get("timezone")),
+            });
+        }
+
+        Ok(res)
+    }
+
+    async fn add_task(&self, schedule: Task) -> Result<Task, DatabaseErrors> {
+        match self.get_user_id(&schedule.user_id).await {
+            Ok(Some(_)) => (),
+            Ok(None) => return Err(DatabaseErrors::UserDoesNotExist),
+            Err(e) => return Err(e),
+        };
+
+        match sqlx::query("INSERT INTO schedule (guildid, userid, task, tasksecondary, interval, times, nextrun, enddate, maxruns) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) RETURNING id")
+            .bind(schedule.guild_id)
+            .bind(schedule.user_id)
+            .bind(&schedule.task)
+            .bind(&schedule.task_secondary)
+            .bind(schedule.interval.microseconds)
+            .bind(times_to_text(&schedule.times))
+            .bind(schedule.next_run)
+            .bind(schedule.end_date)
+            .bind(schedule.max_runs)
+            .fetch_one(&self.db)
+            .await
+        {
+            Ok(e) => Ok(Task {
+                id: e.get("id"),
+                ..schedule
+            }),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn delete_task(&self, id: &i64) -> Result<(), DatabaseErrors> {
+        match sqlx::query("UPDATE schedule SET archived = ?2, archivereason = 'deleted' WHERE id = ?1 AND archived IS NULL")
+            .bind(id)
+            .bind(Utc::now())
+            .execute(&self.db)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn delete_task_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors> {
+        match sqlx::query("UPDATE schedule SET archived = ?3, archivereason = 'deleted' WHERE guildid = ?1 AND userid = ?2 AND archived IS NULL")
+            .bind(guild_id)
+            .bind(user_id)
+            .bind(Utc::now())
+            .execute(&self.db)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn get_archived_task_id(&self, id: &i64) -> Result<Option<ArchivedTask>, DatabaseErrors> {
+        let opt = match sqlx::query("SELECT s.id, s.guildid, u.userid, s.task, s.enddate, s.archived, s.archivereason, u.timezone FROM schedule s INNER JOIN users u ON s.userid = u.id AND s.id = ?1 AND s.archived IS NOT NULL")
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match opt {
+            Some(e) => match archived_from_row(&e) {
+                Ok(t) => Ok(Some(t)),
+                Err(e) => Err(DatabaseErrors::Query(e)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn get_archived_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+        reason: &str,
+    ) -> Result<Vec<ArchivedTask>, DatabaseErrors> {
+        let rows = match sqlx::query("SELECT s.id, s.guildid, u.userid, s.task, s.enddate, s.archived, s.archivereason, u.timezone FROM schedule s INNER JOIN users u ON s.userid = u.id AND s.guildid = ?1 WHERE s.archivereason = ?3 AND (?2 IS NULL OR u.userid = ?2) ORDER BY s.archived DESC")
+            .bind(guild_id)
+            .bind(user_id)
+            .bind(reason)
+            .fetch_all(&self.db)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match rows.iter().map(archived_from_row).collect() {
+            Ok(e) => Ok(e),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn pause_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+        reason: &str,
+        now: &DateTime<Utc>,
+    ) -> Result<u64, DatabaseErrors> {
+        match sqlx::query("UPDATE schedule SET archived = ?4, archivereason = ?3 WHERE guildid = ?1 AND archived IS NULL AND (?2 IS NULL OR userid IN (SELECT id FROM users WHERE userid = ?2))")
+            .bind(guild_id)
+            .bind(user_id)
+            .bind(reason)
+            .bind(now)
+            .execute(&self.db)
+            .await
+        {
+            Ok(e) => Ok(e.rows_affected()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn restore_task(&self, id: &i64, now: &DateTime<Utc>) -> Result<Task, DatabaseErrors> {
+        let mut tx = match self.db.begin().await {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let e = match sqlx::query("SELECT s.userid AS uid, s.guildid, s.interval, s.times, s.nextrun, u.userid, u.timezone, u.archived FROM schedule s INNER JOIN users u ON s.userid = u.id AND s.id = ?1 AND s.archived IS NOT NULL")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+        {
+            Ok(Some(e)) => e,
+            Ok(None) => return Err(DatabaseErrors::DoesNotExist),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let uid: i64 = e.get("uid");
+        let mut owner = uid;
+
+        if e.get::<Option<DateTime<Utc>>, _>("archived").is_some() {
+            // The user may have been added again since they were deleted
+            let active = match sqlx::query(
+                "SELECT id FROM users WHERE guildid = ?1 AND userid = ?2 AND archived IS NULL",
+            )
+            .bind(e.get::<i64, _>("guildid"))
+            .bind(e.get::<i64, _>("userid"))
+            .fetch_optional(&mut *tx)
+            .await
+            {
+                Ok(e) => e,
+                Err(e) => return Err(DatabaseErrors::Query(e)),
+            };
+
+            match active {
+                Some(u) => owner = u.get("id"),
+                None => {
+                    if let Err(e) = sqlx::query("UPDATE users SET archived = NULL WHERE id = ?1")
+                        .bind(uid)
+                        .execute(&mut *tx)
+                        .await
+                    {
+                        return Err(DatabaseErrors::Query(e));
+                    }
+                }
+            }
+        }
+
+        let interval = to_interval(e.get("interval"));
+        let times = text_to_times(e.get("times"));
+        let timezone = to_interval(e.get("timezone"));
+
+        let mut next_run: DateTime<Utc> = e.get("nextrun");
+        while next_run <= *now {
+            next_run = crate::util::next_occurrence(&next_run, &interval, &times, &timezone);
+        }
+
+        if let Err(e) = sqlx::query("UPDATE schedule SET archived = NULL, archivereason = NULL, userid = ?2, nextrun = ?3 WHERE id = ?1")
+            .bind(id)
+            .bind(owner)
+            .bind(next_run)
+            .execute(&mut *tx)
+            .await
+        {
+            return Err(DatabaseErrors::Query(e));
+        }
+
+        if let Err(e) = tx.commit().await {
+            return Err(DatabaseErrors::Query(e));
+        }
+
+        match self.get_task_id(id).await {
+            Ok(Some(e)) => Ok(e),
+            Ok(None) => Err(DatabaseErrors::DoesNotExist),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn purge_archived(&self, before: &DateTime<Utc>) -> Result<u64, DatabaseErrors> {
+        let deliveries = match sqlx::query("DELETE FROM deliveries WHERE sent < ?1 OR failed < ?1")
+            .bind(before)
+            .execute(&self.db)
+            .await
+        {
+            Ok(e) => e.rows_affected(),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let schedules = match sqlx::query("DELETE FROM schedule WHERE archived < ?1")
+            .bind(before)
+            .execute(&self.db)
+            .await
+        {
+            Ok(e) => e.rows_affected(),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let users = match sqlx::query("DELETE FROM users WHERE (archived < ?1 OR guildid IN (SELECT guildid FROM guilds WHERE removed < ?1)) AND NOT EXISTS (SELECT 1 FROM schedule s WHERE s.userid = users.id)")
+            .bind(before)
+            .execute(&self.db)
+            .await
+        {
+            Ok(e) => e.rows_affected(),
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match sqlx::query("DELETE FROM guilds WHERE removed < ?1 AND NOT EXISTS (SELECT 1 FROM users u WHERE u.guildid = guilds.guildid) AND NOT EXISTS (SELECT 1 FROM schedule s WHERE s.guildid = guilds.guildid)")
+            .bind(before)
+            .execute(&self.db)
+            .await
+        {
+            Ok(e) => Ok(deliveries + schedules + users + e.rows_affected()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn enqueue_due(&self, now: &DateTime<Utc>) -> Result<u64, DatabaseErrors> {
+        let mut tx = match self.db.begin().await {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let rows = match sqlx::query(&format!(
+            "SELECT {}, g.channel, u.userid AS discordid, u.praise, u.praisename, u.timezone FROM schedule s INNER JOIN users u ON s.userid = u.id INNER JOIN guilds g ON s.guildid = g.guildid WHERE s.archived IS NULL AND s.nextrun < ?1",
+            TASK_COLUMNS
+        ))
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let schedules: Vec<Schedule> = match rows.iter().map(schedule_from_row).collect() {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let mut queued = 0;
+
+        for schedule in &schedules {
+            let res = match schedule.is_final_run() {
+                true => sqlx::query("UPDATE schedule SET runs = runs + 1, archived = ?2, archivereason = 'finished' WHERE id = ?1")
+                    .bind(schedule.id)
+                    .bind(now)
+                    .execute(&mut *tx)
+                    .await,
+                false => sqlx::query("UPDATE schedule SET nextrun = ?2, runs = runs + 1 WHERE id = ?1")
+                    .bind(schedule.id)
+                    .bind(crate::util::next_occurrence(
+                        &schedule.next_run,
+                        &schedule.interval,
+                        &schedule.times,
+                        &schedule.timezone,
+                    ))
+                    .execute(&mut *tx)
+                    .await,
+            };
+
+            if let Err(e) = res {
+                return Err(DatabaseErrors::Query(e));
+            }
+
+            match sqlx::query("INSERT INTO deliveries (scheduleid, run, runat, nextattempt) VALUES (?1, ?2, ?3, ?4) ON CONFLICT (scheduleid, run) DO NOTHING")
+                .bind(schedule.id)
+                .bind(schedule.runs)
+                .bind(schedule.next_run)
+                .bind(now)
+                .execute(&mut *tx)
+                .await
+            {
+                Ok(e) => queued += e.rows_affected(),
+                Err(e) => return Err(DatabaseErrors::Query(e)),
+            };
+        }
+
+        // If another connection wrote since our SELECT, SQLite refuses to upgrade this
+        // transaction to a write and it fails rather than queueing the runs twice
+        match tx.commit().await {
+            Ok(_) => Ok(queued),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn next_wakeup(&self) -> Result<Option<DateTime<Utc>>, DatabaseErrors> {
+        // Timestamps are all stored in the same RFC 3339 form so they compare as text
+        match sqlx::query_scalar("SELECT MIN(t) FROM (SELECT MIN(nextrun) AS t FROM schedule WHERE archived IS NULL UNION ALL SELECT MIN(MAX(d.nextattempt, COALESCE(d.leaseduntil, d.nextattempt))) AS t FROM deliveries d INNER JOIN schedule s ON d.scheduleid = s.id WHERE d.sent IS NULL AND d.failed IS NULL AND (s.archivereason IS NULL OR s.archivereason = 'finished'))")
+            .fetch_one(&self.db)
+            .await
+        {
+            Ok(e) => Ok(e),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn count_due(&self, now: &DateTime<Utc>) -> Result<i64, DatabaseErrors> {
+        match sqlx::query_scalar("SELECT (SELECT COUNT(*) FROM schedule WHERE archived IS NULL AND nextrun <= ?1) + (SELECT COUNT(*) FROM deliveries d INNER JOIN schedule s ON d.scheduleid = s.id WHERE d.sent IS NULL AND d.failed IS NULL AND d.runat <= ?1 AND (s.archivereason IS NULL OR s.archivereason = 'finished'))")
+            .bind(now)
+            .fetch_one(&self.db)
+            .await
+        {
+            Ok(e) => Ok(e),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn claim_deliveries(
+        &self,
+        now: &DateTime<Utc>,
+        owner: &str,
+        lease: &TimeDelta,
+    ) -> Result<Vec<Delivery>, DatabaseErrors> {
+        let mut tx = match self.db.begin().await {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let rows = match sqlx::query(&format!(
+            "SELECT {}, d.id AS deliveryid, d.run, d.runat, d.attempts, g.channel, u.userid AS discordid, u.praise, u.praisename, u.timezone FROM deliveries d INNER JOIN schedule s ON d.scheduleid = s.id INNER JOIN users u ON s.userid = u.id INNER JOIN guilds g ON s.guildid = g.guildid WHERE d.sent IS NULL AND d.failed IS NULL AND d.nextattempt <= ?1 AND (d.leaseduntil IS NULL OR d.leaseduntil < ?1) AND (s.archivereason IS NULL OR s.archivereason = 'finished') ORDER BY d.runat",
+            TASK_COLUMNS
+        ))
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let deliveries: Vec<Delivery> = match rows.iter().map(delivery_from_row).collect() {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let leased_until = *now + *lease;
+
+        for delivery in &deliveries {
+            match sqlx::query("UPDATE deliveries SET leasedby = ?2, leaseduntil = ?3 WHERE id = ?1")
+                .bind(delivery.id)
+                .bind(owner)
+                .bind(leased_until)
+                .execute(&mut *tx)
+                .await
+            {
+                Ok(_) => (),
+                Err(e) => return Err(DatabaseErrors::Query(e)),
+            };
+        }
+
+        match tx.commit().await {
+            Ok(_) => Ok(deliveries),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn mark_delivered(
+        &self,
+        id: &i64,
+        message_id: &i64,
+        now: &DateTime<Utc>,
+    ) -> Result<(), DatabaseErrors> {
+        match sqlx::query("UPDATE deliveries SET messageid = ?2, sent = ?3, sentby = leasedby, attempts = attempts + 1, leasedby = NULL, leaseduntil = NULL WHERE id = ?1")
+            .bind(id)
+            .bind(message_id)
+            .bind(now)
+            .execute(&self.db)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn retry_delivery(
+        &self,
+        id: &i64,
+        retry_at: &DateTime<Utc>,
+        error: &str,
+    ) -> Result<(), DatabaseErrors> {
+        match sqlx::query("UPDATE deliveries SET nextattempt = ?2, lasterror = ?3, attempts = attempts + 1, leasedby = NULL, leaseduntil = NULL WHERE id = ?1")
+            .bind(id)
+            .bind(retry_at)
+            .bind(error)
+            .execute(&self.db)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn fail_delivery(
+        &self,
+        id: &i64,
+        now: &DateTime<Utc>,
+        error: &str,
+    ) -> Result<(), DatabaseErrors> {
+        match sqlx::query("UPDATE deliveries SET failed = ?2, lasterror = ?3, attempts = attempts + 1, leasedby = NULL, leaseduntil = NULL WHERE id = ?1")
+            .bind(id)
+            .bind(now)
+            .bind(error)
+            .execute(&self.db)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn sent_by(&self, message_id: &i64, owner: &str) -> Result<bool, DatabaseErrors> {
+        match sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM deliveries WHERE messageid = ?1 AND sentby = ?2)",
+        )
+        .bind(message_id)
+        .bind(owner)
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(e) => Ok(e),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn shift_schedules(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+        interval: &PgInterval,
+    ) -> Result<(), DatabaseErrors> {
+        let uid = match self.get_user_guild(guild_id, user_id).await {
+            Ok(Some(e)) => e.id,
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let delta = chrono::TimeDelta::microseconds(interval.microseconds);
+
+        let mut tx = match self.db.begin().await {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        let rows = match sqlx::query("SELECT id, nextrun, times FROM schedule WHERE userid = ?1 AND guildid = ?2 AND archived IS NULL")
+            .bind(uid)
+            .bind(guild_id)
+            .fetch_all(&mut *tx)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        for e in rows {
+            let next_run: DateTime<Utc> = e.get("nextrun");
+
+            // Times of day wrap around midnight like TIME + INTERVAL does in Postgres
+            let mut times: Vec<NaiveTime> = text_to_times(e.get("times"))
+                .iter()
+                .map(|t| t.overflowing_add_signed(delta).0)
+                .collect();
+            times.sort();
+
+            if let Err(e) =
+                sqlx::query("UPDATE schedule SET nextrun = ?2, times = ?3 WHERE id = ?1")
+                    .bind(e.get::<i64, _>("id"))
+                    .bind(next_run + delta)
+                    .bind(times_to_text(&times))
+                    .execute(&mut *tx)
+                    .await
+            {
+                return Err(DatabaseErrors::Query(e));
+            }
+        }
+
+        match tx.commit().await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn get_user_id(&self, id: &i64) -> Result<Option<User>, DatabaseErrors> {
+        let opt = match sqlx::query("SELECT * FROM users WHERE id = ?1 AND archived IS NULL")
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match opt {
+            Some(e) => match user_from_row(&e) {
+                Ok(u) => Ok(Some(u)),
+                Err(e) => Err(DatabaseErrors::Query(e)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn get_users_guild(&self, guild_id: &i64) -> Result<Vec<User>, DatabaseErrors> {
+        let rows = match sqlx::query("SELECT * FROM users WHERE guildid = ?1 AND archived IS NULL")
+            .bind(guild_id)
+            .fetch_all(&self.db)
+            .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match rows.iter().map(user_from_row).collect() {
+            Ok(e) => Ok(e),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn get_user_guild(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+    ) -> Result<Option<User>, DatabaseErrors> {
+        let opt = match sqlx::query(
+            "SELECT * FROM users WHERE guildid = ?1 AND userid = ?2 AND archived IS NULL",
+        )
+        .bind(guild_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await
+        {
+            Ok(e) => e,
+            Err(e) => return Err(DatabaseErrors::Query(e)),
+        };
+
+        match opt {
+            Some(e) => match user_from_row(&e) {
+                Ok(u) => Ok(Some(u)),
+                Err(e) => Err(DatabaseErrors::Query(e)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn update_user(&self, user: &User) -> Result<User, DatabaseErrors> {
+        let e = match self.get_user_guild(&user.guild_id, &user.user_id).await {
+            Ok(Some(e)) => e,
+            Ok(None) => return Err(DatabaseErrors::UserDoesNotExist),
+            Err(e) => return Err(e),
+        };
+
+        match sqlx::query(
+            "UPDATE users SET praise = ?1, praisename = ?2, timezone = ?3 WHERE id = ?4",
+        )
+        .bind(&user.praise)
+        .bind(&user.praise_name)
+        .bind(user.timezone.microseconds)
+        .bind(e.id)
+        .execute(&self.db)
+        .await
+        {
+            Ok(_) => Ok(user.clone()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn delete_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors> {
+        let e = match self.get_user_guild(guild_id, user_id).await {
+            Ok(Some(e)) => e,
+            Ok(None) => return Err(DatabaseErrors::UserDoesNotExist),
+            Err(e) => return Err(e),
+        };
+
+        match self.delete_task_user(guild_id, &e.id).await {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        };
+
+        match sqlx::query("UPDATE users SET archived = ?2 WHERE id = ?1")
+            .bind(e.id)
+            .bind(Utc::now())
+            .execute(&self.db)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+
+    async fn add_user(&self, user: &User) -> Result<User, DatabaseErrors> {
+        match self.get_guild(&user.guild_id).await {
+            Ok(Some(_)) => (),
+            Ok(None) => return Err(DatabaseErrors::GuildDoesNotExist),
+            Err(e) => return Err(e),
+        };
+
+        match self.get_user_guild(&user.guild_id, &user.user_id).await {
+            Ok(Some(_)) => return Err(DatabaseErrors::UserAlreadyExists),
+            Ok(None) => (),
+            Err(e) => return Err(e),
+        };
+
+        match sqlx::query("INSERT INTO users (guildid, userid, praise, praisename, timezone) VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id")
+            .bind(user.guild_id)
+            .bind(user.user_id)
+            .bind(&user.praise)
+            .bind(&user.praise_name)
+            .bind(user.timezone.microseconds)
+            .fetch_one(&self.db)
+            .await
+        {
+            Ok(e) => Ok(User {
+                id: e.get("id"),
+                ..user.clone()
+            }),
+            Err(e) => Err(DatabaseErrors::Query(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::repo::testing::*;
+
+    async fn database() -> SqliteDatabase {
+        SqliteDatabase::new("sqlite::memory:".to_owned())
+            .await
+            .unwrap()
+    }
+
+    /// Twice a day at 08:00 and 20:00 local, three times in total
+    fn twice_daily(user: &User, next_run: DateTime<Utc>) -> Task {
+        Task {
+            times: vec![
+                NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+            ],
+            max_runs: Some(3),
+            ..task(user, next_run)
+        }
+    }
+
+    #[tokio::test]
+    async fn task_round_trips() {
+        let (db, user) = setup(database().await, TimeDelta::hours(10)).await;
+
+        let t = db
+            .add_task(twice_daily(&user, at("2024-12-31T22:00:00Z")))
+            .await
+            .unwrap();
+
+        let tasks = db.get_task_user(&100, &300).await.unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].id, t.id);
+        assert_eq!(tasks[0].times, t.times);
+        assert_eq!(tasks[0].interval, t.interval);
+        assert_eq!(tasks[0].next_run, t.next_run);
+        assert_eq!(tasks[0].timezone, user.timezone);
+        assert_eq!(db.next_wakeup().await.unwrap(), Some(t.next_run));
+        assert_eq!(db.count_due(&t.next_run).await.unwrap(), 1);
+
+        assert_eq!(
+            db.enqueue_due(&at("2024-12-31T21:00:00Z")).await.unwrap(),
+            0
+        );
+        assert_eq!(
+            db.enqueue_due(&at("2024-12-31T23:00:00Z")).await.unwrap(),
+            1
+        );
+
+        let due = db
+            .claim_deliveries(&at("2024-12-31T23:00:00Z"), "a", &TimeDelta::minutes(5))
+            .await
+            .unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].schedule.channel_id, 200);
+        assert_eq!(due[0].schedule.next_run, at("2024-12-31T22:00:00Z"));
+    }
+
+    #[tokio::test]
+    async fn enqueue_until_finished() {
+        let (db, user) = setup(database().await, TimeDelta::hours(10)).await;
+
+        let t = db
+            .add_task(twice_daily(&user, at("2024-12-31T22:00:00Z")))
+            .await
+            .unwrap();
+
+        let now = at("2025-01-02T00:00:00Z");
+
+        assert_eq!(db.enqueue_due(&now).await.unwrap(), 1);
+        assert_eq!(
+            db.get_task_id(&t.id).await.unwrap().unwrap().next_run,
+            at("2025-01-01T10:00:00Z")
+        );
+
+        assert_eq!(db.enqueue_due(&now).await.unwrap(), 1);
+        assert_eq!(db.enqueue_due(&now).await.unwrap(), 1);
+        assert!(db.get_task_id(&t.id).await.unwrap().is_none());
+        assert_eq!(
+            db.get_archived_task_id(&t.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .reason,
+            "finished"
+        );
+
+        let deliveries = db
+            .claim_deliveries(&now, "a", &TimeDelta::minutes(5))
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 3);
+        assert!(deliveries[2].schedule.is_final_run());
+    }
+
+    #[tokio::test]
+    async fn delivery_is_leased_retried_and_sent() {
+        let (db, user) = setup(database().await, TimeDelta::hours(10)).await;
+
+        db.add_task(twice_daily(&user, at("2024-12-31T22:00:00Z")))
+            .await
+            .unwrap();
+
+        let now = at("2025-01-01T00:00:00Z");
+        let lease = TimeDelta::minutes(5);
+
+        db.enqueue_due(&now).await.unwrap();
+
+        let claimed = db.claim_deliveries(&now, "a", &lease).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert!(db
+            .claim_deliveries(&now, "b", &lease)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let retry_at = now + TimeDelta::minutes(1);
+        db.retry_delivery(&claimed[0].id, &retry_at, "timed out")
+            .await
+            .unwrap();
+
+        let retried = db.claim_deliveries(&retry_at, "b", &lease).await.unwrap();
+        assert_eq!(retried[0].attempts, 1);
+
+        db.mark_delivered(&retried[0].id, &1234, &retry_at)
+            .await
+            .unwrap();
+        assert!(db.sent_by(&1234, "b").await.unwrap());
+        assert!(!db.sent_by(&1234, "a").await.unwrap());
+        assert!(db
+            .claim_deliveries(&(retry_at + TimeDelta::hours(1)), "a", &lease)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            db.purge_archived(&(retry_at + TimeDelta::minutes(1)))
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_restore_and_purge() {
+        let (db, user) = setup(database().await, TimeDelta::hours(10)).await;
+
+        let t = db
+            .add_task(twice_daily(&user, at("2024-12-31T22:00:00Z")))
+            .await
+            .unwrap();
+
+        db.delete_user(&100, &300).await.unwrap();
+        assert!(db.get_user_guild(&100, &300).await.unwrap().is_none());
+        assert_eq!(
+            db.get_deleted_tasks(&100, Some(&300)).await.unwrap().len(),
+            1
+        );
+
+        let restored = db.restore_task(&t.id, &Utc::now()).await.unwrap();
+        assert!(restored.next_run > Utc::now());
+        assert!(db.get_user_guild(&100, &300).await.unwrap().is_some());
+
+        db.delete_task(&t.id).await.unwrap();
+        assert_eq!(
+            db.purge_archived(&(Utc::now() + TimeDelta::minutes(1)))
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_guild_is_paused_resumed_and_purged() {
+        let (db, user) = setup(database().await, TimeDelta::hours(10)).await;
+
+        let t = db
+            .add_task(twice_daily(&user, at("2024-12-31T22:00:00Z")))
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        db.set_guild_removed(&100, Some(&now)).await.unwrap();
+        assert_eq!(
+            db.pause_tasks(&100, None, PAUSED_GUILD_REMOVED, &now)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(db.get_task_id(&t.id).await.unwrap().is_none());
+
+        db.set_guild_removed(&100, None).await.unwrap();
+        assert_eq!(
+            db.resume_tasks(&100, None, PAUSED_GUILD_REMOVED, &now)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(db.get_task_id(&t.id).await.unwrap().unwrap().next_run > now);
+
+        db.set_guild_removed(&100, Some(&now)).await.unwrap();
+        db.pause_tasks(&100, Some(&300), PAUSED_GUILD_REMOVED, &now)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.purge_archived(&(now + TimeDelta::minutes(1)))
+                .await
+                .unwrap(),
+            3
+        );
+        assert!(db.get_guild(&100).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn shift_wraps_times() {
+        let (db, user) = setup(database().await, TimeDelta::hours(10)).await;
+
+        let t = db
+            .add_task(twice_daily(&user, at("2024-12-31T22:00:00Z")))
+            .await
+            .unwrap();
+
+        db.shift_schedules(
+            &100,
+            &300,
+            &to_interval(TimeDelta::hours(5).num_microseconds().unwrap()),
+        )
+        .await
+        .unwrap();
+
+        let t = db.get_task_id(&t.id).await.unwrap().unwrap();
+        assert_eq!(t.next_run, at("2025-01-01T03:00:00Z"));
+        assert_eq!(
+            t.times,
+            vec![
+                NaiveTime::from_hms_opt(1, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
+            ]
+        );
+    }
+}
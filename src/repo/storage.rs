@@ -0,0 +1,356 @@
+use async_trait::async_trait;
+
+use chrono::TimeDelta;
+use sqlx::migrate::MigrateError;
+use sqlx::postgres::types::PgInterval;
+use sqlx::types::chrono::{DateTime, NaiveTime, Utc};
+
+#[derive(Clone, Debug)]
+pub struct Guild {
+    pub id: i64,
+    pub channel: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub id: i64,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub channel_id: i64,
+    pub task: String,
+    pub task_secondary: String,
+    pub praise: String,
+    pub praise_name: String,
+    pub interval: PgInterval,
+    pub times: Vec<NaiveTime>,
+    pub timezone: PgInterval,
+    pub created: DateTime<Utc>,
+    pub next_run: DateTime<Utc>,
+    pub end_date: Option<DateTime<Utc>>,
+    pub max_runs: Option<i32>,
+    pub runs: i32,
+}
+
+impl Schedule {
+    /// Whether the occurrence at `next_run` is the last one before an end condition is hit
+    pub fn is_final_run(&self) -> bool {
+        crate::util::is_final_run(
+            &self.next_run,
+            &self.interval,
+            &self.times,
+            &self.timezone,
+            self.runs,
+            self.max_runs,
+            self.end_date,
+        )
+    }
+}
+
+/// A queued run of a schedule waiting to be sent
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    pub id: i64,
+    /// The schedule as it was for this run
+    pub schedule: Schedule,
+    pub attempts: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Task {
+    pub id: i64,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub task: String,
+    pub task_secondary: String,
+    pub interval: PgInterval,
+    pub times: Vec<NaiveTime>,
+    pub created: DateTime<Utc>,
+    pub next_run: DateTime<Utc>,
+    pub end_date: Option<DateTime<Utc>>,
+    pub max_runs: Option<i32>,
+    pub runs: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct UserTask {
+    pub id: i64,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub task: String,
+    pub task_secondary: String,
+    pub interval: PgInterval,
+    pub times: Vec<NaiveTime>,
+    pub created: DateTime<Utc>,
+    pub next_run: DateTime<Utc>,
+    pub end_date: Option<DateTime<Utc>>,
+    pub max_runs: Option<i32>,
+    pub runs: i32,
+    pub timezone: PgInterval,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArchivedTask {
+    pub id: i64,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub task: String,
+    pub end_date: Option<DateTime<Utc>>,
+    pub archived: DateTime<Utc>,
+    pub reason: String,
+    pub timezone: PgInterval,
+}
+
+#[derive(Clone, Debug)]
+pub struct User {
+    pub id: i64,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub praise: String,
+    pub praise_name: String,
+    pub timezone: PgInterval,
+}
+
+#[derive(Clone, Debug)]
+pub struct Timezone {
+    timezone: i16,
+}
+
+/// Archive reason for tasks paused because puppy was removed from their guild
+pub const PAUSED_GUILD_REMOVED: &str = "guild_removed";
+
+/// Archive reason for tasks paused because their owner left the guild
+pub const PAUSED_MEMBER_LEFT: &str = "member_left";
+
+/// Archive reason for tasks paused because their guild's channel was deleted
+pub const PAUSED_CHANNEL_DELETED: &str = "channel_deleted";
+
+#[derive(Debug)]
+pub enum DatabaseErrors {
+    CannotConect,
+    MigrationFolderDoesNotExist,
+    MigrationError(MigrateError),
+    DoesNotExist,
+    GuildDoesNotExist,
+    UserDoesNotExist,
+    UserAlreadyExists,
+    /// The url needs a backend this build was compiled without
+    #[cfg_attr(feature = "sqlite", allow(dead_code))]
+    UnsupportedDatabase,
+    /// A query failed, the cause is kept for the logs
+    Query(sqlx::Error),
+}
+
+impl std::fmt::Display for DatabaseErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseErrors::CannotConect => write!(f, "cannot connect to the database"),
+            DatabaseErrors::MigrationFolderDoesNotExist => write!(f, "migration folder is missing"),
+            DatabaseErrors::MigrationError(_) => write!(f, "migration failed"),
+            DatabaseErrors::DoesNotExist => write!(f, "does not exist"),
+            DatabaseErrors::GuildDoesNotExist => write!(f, "guild does not exist"),
+            DatabaseErrors::UserDoesNotExist => write!(f, "user does not exist"),
+            DatabaseErrors::UserAlreadyExists => write!(f, "user already exists"),
+            DatabaseErrors::UnsupportedDatabase => write!(f, "unsupported database url"),
+            DatabaseErrors::Query(_) => write!(f, "query failed"),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseErrors {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DatabaseErrors::MigrationError(e) => Some(e),
+            DatabaseErrors::Query(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Everything the bot needs to persist guilds, users and their schedules.
+/// `Database` is the Postgres implementation.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn get_guild(&self, guild: &i64) -> Result<Option<Guild>, DatabaseErrors>;
+
+    /// Inserts the guild or updates its channel if it already exists
+    async fn update_guild(&self, guild: &Guild) -> Result<Guild, DatabaseErrors>;
+
+    /// Records when puppy was removed from a guild, or clears it when puppy is
+    /// back. A removed guild and its users are purged along with its schedules.
+    async fn set_guild_removed(
+        &self,
+        guild_id: &i64,
+        removed: Option<&DateTime<Utc>>,
+    ) -> Result<(), DatabaseErrors>;
+
+    async fn get_task_id(&self, id: &i64) -> Result<Option<Task>, DatabaseErrors>;
+
+    /// Active tasks for a discord user in a guild
+    async fn get_task_user(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+    ) -> Result<Vec<UserTask>, DatabaseErrors>;
+
+    async fn get_task_guild(&self, guild_id: &i64) -> Result<Vec<UserTask>, DatabaseErrors> {
+        let users = match self.get_users_guild(guild_id).await {
+            Ok(e) => e,
+            Err(e) => return Err(e),
+        };
+
+        let mut res: Vec<UserTask> = Vec::new();
+
+        for user in users {
+            match self.get_task_user(guild_id, &user.user_id).await {
+                Ok(e) => res.extend(e),
+                Err(e) => return Err(e),
+            };
+        }
+
+        Ok(res)
+    }
+
+    /// Adds a task for `schedule.user_id`, which is the users table id
+    async fn add_task(&self, schedule: Task) -> Result<Task, DatabaseErrors>;
+
+    /// Archives a task, it can be restored until it is purged
+    async fn delete_task(&self, id: &i64) -> Result<(), DatabaseErrors>;
+
+    async fn delete_task_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors>;
+
+    async fn get_archived_task_id(&self, id: &i64) -> Result<Option<ArchivedTask>, DatabaseErrors>;
+
+    /// Tasks in a guild archived for `reason` that can still be restored, optionally
+    /// only for one user, most recent first
+    async fn get_archived_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+        reason: &str,
+    ) -> Result<Vec<ArchivedTask>, DatabaseErrors>;
+
+    /// Deleted tasks in a guild that can still be restored, optionally only for one user
+    async fn get_deleted_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+    ) -> Result<Vec<ArchivedTask>, DatabaseErrors> {
+        self.get_archived_tasks(guild_id, user_id, "deleted").await
+    }
+
+    /// Archives the active tasks in a guild, optionally only for one discord user,
+    /// with `reason` so `resume_tasks` can bring back just those. Their queued
+    /// deliveries are held back too. Returns how many were paused.
+    async fn pause_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+        reason: &str,
+        now: &DateTime<Utc>,
+    ) -> Result<u64, DatabaseErrors>;
+
+    /// Restores the tasks `pause_tasks` archived for `reason` that have not been
+    /// purged yet. Returns how many were resumed.
+    async fn resume_tasks(
+        &self,
+        guild_id: &i64,
+        user_id: Option<&i64>,
+        reason: &str,
+        now: &DateTime<Utc>,
+    ) -> Result<u64, DatabaseErrors> {
+        let tasks = match self.get_archived_tasks(guild_id, user_id, reason).await {
+            Ok(e) => e,
+            Err(e) => return Err(e),
+        };
+
+        for task in &tasks {
+            self.restore_task(&task.id, now).await?;
+        }
+
+        Ok(tasks.len() as u64)
+    }
+
+    /// Brings back an archived task, restoring its owner if they were archived too
+    /// and skipping any occurrences that were missed before `now`.
+    async fn restore_task(&self, id: &i64, now: &DateTime<Utc>) -> Result<Task, DatabaseErrors>;
+
+    /// Permanently removes schedules and users archived before `before` and guilds
+    /// puppy was removed from before then, along with deliveries sent or given up on
+    async fn purge_archived(&self, before: &DateTime<Utc>) -> Result<u64, DatabaseErrors>;
+
+    /// Moves every task due before `now` on to its next occurrence and queues a
+    /// delivery for the occurrence in the same transaction, so a run is never
+    /// lost or queued twice. Returns how many deliveries were queued.
+    async fn enqueue_due(&self, now: &DateTime<Utc>) -> Result<u64, DatabaseErrors>;
+
+    /// The earliest time a task falls due or a queued delivery can be sent,
+    /// so the scheduler can sleep until then
+    async fn next_wakeup(&self) -> Result<Option<DateTime<Utc>>, DatabaseErrors>;
+
+    /// How many runs were due by `now` but haven't gone out yet, whether or not
+    /// they have been queued
+    async fn count_due(&self, now: &DateTime<Utc>) -> Result<i64, DatabaseErrors>;
+
+    /// Leases the deliveries ready to be sent to `owner`, so several instances
+    /// never send the same one. Deliveries leased by another instance are skipped
+    /// until the lease runs out.
+    async fn claim_deliveries(
+        &self,
+        now: &DateTime<Utc>,
+        owner: &str,
+        lease: &TimeDelta,
+    ) -> Result<Vec<Delivery>, DatabaseErrors>;
+
+    /// Records that a delivery went out as `message_id`
+    async fn mark_delivered(
+        &self,
+        id: &i64,
+        message_id: &i64,
+        now: &DateTime<Utc>,
+    ) -> Result<(), DatabaseErrors>;
+
+    /// Gives up the lease on a delivery that could not be sent so it is tried again at `retry_at`
+    async fn retry_delivery(
+        &self,
+        id: &i64,
+        retry_at: &DateTime<Utc>,
+        error: &str,
+    ) -> Result<(), DatabaseErrors>;
+
+    /// Gives up on a delivery for good, it is kept until purged so the error can be looked at
+    async fn fail_delivery(
+        &self,
+        id: &i64,
+        now: &DateTime<Utc>,
+        error: &str,
+    ) -> Result<(), DatabaseErrors>;
+
+    /// Whether `owner` sent the reminder posted as `message_id`, false for any
+    /// other message
+    async fn sent_by(&self, message_id: &i64, owner: &str) -> Result<bool, DatabaseErrors>;
+
+    /// Shifts all schedules for a user by an interval
+    async fn shift_schedules(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+        interval: &PgInterval,
+    ) -> Result<(), DatabaseErrors>;
+
+    async fn get_user_id(&self, id: &i64) -> Result<Option<User>, DatabaseErrors>;
+
+    async fn get_users_guild(&self, guild_id: &i64) -> Result<Vec<User>, DatabaseErrors>;
+
+    async fn get_user_guild(
+        &self,
+        guild_id: &i64,
+        user_id: &i64,
+    ) -> Result<Option<User>, DatabaseErrors>;
+
+    async fn update_user(&self, user: &User) -> Result<User, DatabaseErrors>;
+
+    /// Archives a user along with all of their schedules
+    async fn delete_user(&self, guild_id: &i64, user_id: &i64) -> Result<(), DatabaseErrors>;
+
+    async fn add_user(&self, user: &User) -> Result<User, DatabaseErrors>;
+}
@@ -0,0 +1,67 @@
+//! Fixtures shared by the storage backend and scheduler tests
+
+use chrono::TimeDelta;
+use sqlx::postgres::types::PgInterval;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::repo::schedule::LEASE;
+use crate::repo::storage::*;
+
+pub const GUILD: i64 = 100;
+pub const CHANNEL: i64 = 200;
+pub const MEMBER: i64 = 300;
+
+pub fn at(datetime: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(datetime).unwrap().to_utc()
+}
+
+pub fn interval(delta: TimeDelta) -> PgInterval {
+    PgInterval::try_from(delta).unwrap()
+}
+
+/// Gives puppy a home in `GUILD` and makes `MEMBER` a friend with `timezone`
+pub async fn setup<S: Storage>(db: S, timezone: TimeDelta) -> (S, User) {
+    db.update_guild(&Guild {
+        id: GUILD,
+        channel: CHANNEL,
+    })
+    .await
+    .unwrap();
+
+    let user = db
+        .add_user(&User {
+            id: 0,
+            guild_id: GUILD,
+            user_id: MEMBER,
+            praise: "pats".to_owned(),
+            praise_name: "good pup".to_owned(),
+            timezone: interval(timezone),
+        })
+        .await
+        .unwrap();
+
+    (db, user)
+}
+
+/// A daily task for `user` first due at `next_run`
+pub fn task(user: &User, next_run: DateTime<Utc>) -> Task {
+    Task {
+        id: 0,
+        guild_id: GUILD,
+        user_id: user.id,
+        task: "take meds".to_owned(),
+        task_secondary: "taken meds".to_owned(),
+        interval: interval(TimeDelta::days(1)),
+        times: Vec::new(),
+        created: next_run,
+        next_run,
+        end_date: None,
+        max_runs: None,
+        runs: 0,
+    }
+}
+
+/// Claims deliveries for `owner` with the scheduler's lease
+pub async fn claim(db: &dyn Storage, now: &DateTime<Utc>, owner: &str) -> Vec<Delivery> {
+    db.claim_deliveries(now, owner, &LEASE).await.unwrap()
+}
@@ -1,41 +1,47 @@
-use chrono::TimeDelta;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeDelta, Utc};
 use sqlx::postgres::types::PgInterval;
 
+use crate::error::Error;
+
 pub fn pginterval_to_string(interval: &PgInterval) -> String {
-    let mut delta = TimeDelta::microseconds(interval.microseconds);
+    timedelta_to_string(&TimeDelta::microseconds(interval.microseconds))
+}
+
+pub fn timedelta_to_string(delta: &TimeDelta) -> String {
+    let mut delta = *delta;
 
     let days = delta.num_days();
 
-    delta = delta - TimeDelta::days(days);
+    delta -= TimeDelta::days(days);
 
     let hours = delta.num_hours();
 
-    delta = delta - TimeDelta::hours(hours);
+    delta -= TimeDelta::hours(hours);
 
     let mins = delta.num_minutes();
 
-    let mut res = String::new();
+    let mut res: Vec<String> = Vec::new();
 
     if days > 0 {
         match days == 1 {
-            true => res = format!("{} day ", days),
-            false => res = format!("{} days ", days),
+            true => res.push(format!("{} day", days)),
+            false => res.push(format!("{} days", days)),
         }
     }
     if hours > 0 {
         match hours == 1 {
-            true => res = format!("{} hour ", hours),
-            false => res = format!("{} hours ", hours),
+            true => res.push(format!("{} hour", hours)),
+            false => res.push(format!("{} hours", hours)),
         }
     }
     if mins > 0 {
         match mins == 1 {
-            true => res = format!("{} minute", mins),
-            false => res = format!("{} minutes", mins),
+            true => res.push(format!("{} minute", mins)),
+            false => res.push(format!("{} minutes", mins)),
         }
     }
 
-    return res;
+    res.join(" ")
 }
 
 pub fn format_timezone(interval: &PgInterval) -> String {
@@ -53,3 +59,419 @@ pub fn format_timezone(interval: &PgInterval) -> String {
 
     format!("{}:{:0>#2}", hour, min)
 }
+
+/// Next time the user's local clock reads `time`, strictly after `now`
+pub fn next_local_time(
+    now: DateTime<Utc>,
+    timezone: &PgInterval,
+    time: NaiveTime,
+) -> DateTime<Utc> {
+    let offset = TimeDelta::microseconds(timezone.microseconds);
+    let local_now = now.naive_utc() + offset;
+
+    let mut local = local_now.date().and_time(time);
+
+    if local <= local_now {
+        local += TimeDelta::days(1);
+    }
+
+    (local - offset).and_utc()
+}
+
+/// When a new task first fires, the next time the user's clock reads `start`
+/// or any of `times` if it fires several times a day
+pub fn first_run(
+    now: DateTime<Utc>,
+    timezone: &PgInterval,
+    start: NaiveTime,
+    times: &[NaiveTime],
+) -> DateTime<Utc> {
+    let mut first = next_local_time(now, timezone, start);
+
+    for t in times {
+        first = first.min(next_local_time(now, timezone, *t));
+    }
+
+    first
+}
+
+/// Formats a UTC datetime in the user's timezone, e.g. `Mon 20 Jan 09:00 +10:00`
+pub fn format_local(datetime: &DateTime<Utc>, timezone: &PgInterval) -> String {
+    let offset = TimeDelta::microseconds(timezone.microseconds).num_seconds() as i32;
+
+    match FixedOffset::east_opt(offset) {
+        Some(e) => datetime
+            .with_timezone(&e)
+            .format("%a %e %b %H:%M %:z")
+            .to_string(),
+        None => datetime.format("%a %e %b %H:%M UTC").to_string(),
+    }
+}
+
+/// Works out when a task runs after the occurrence at `current`.
+/// Tasks without times of day simply repeat every interval, tasks with times
+/// fire at each local time then skip ahead by the interval in whole days.
+pub fn next_occurrence(
+    current: &DateTime<Utc>,
+    interval: &PgInterval,
+    times: &[NaiveTime],
+    timezone: &PgInterval,
+) -> DateTime<Utc> {
+    let interval = TimeDelta::microseconds(interval.microseconds);
+
+    if times.is_empty() {
+        return *current + interval;
+    }
+
+    let offset = TimeDelta::microseconds(timezone.microseconds);
+    let local = current.naive_utc() + offset;
+
+    let mut sorted = times.to_vec();
+    sorted.sort();
+
+    let next = match sorted.iter().find(|t| **t > local.time()) {
+        Some(t) => local.date().and_time(*t),
+        None => (local.date() + TimeDelta::days(interval.num_days().max(1))).and_time(sorted[0]),
+    };
+
+    (next - offset).and_utc()
+}
+
+/// Parses a comma separated list of 24 hour times like `08:00, 14:00, 20:00`
+pub fn parse_times_of_day(times: &str) -> Option<Vec<NaiveTime>> {
+    let mut res: Vec<NaiveTime> = Vec::new();
+
+    for t in times.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        match NaiveTime::parse_from_str(t, "%H:%M") {
+            Ok(e) => res.push(e),
+            Err(_) => return None,
+        }
+    }
+
+    res.sort();
+    res.dedup();
+
+    Some(res)
+}
+
+/// Every time of day a schedule runs at, `start` plus any extra times given as
+/// `08:00, 14:00, 20:00`. Extra times need an interval of whole days.
+pub fn times_with_extra(
+    extra: Option<&str>,
+    start: NaiveTime,
+    interval: &TimeDelta,
+) -> Result<Vec<NaiveTime>, Error> {
+    let extra = match extra {
+        Some(e) => e,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut times = match parse_times_of_day(extra) {
+        Some(e) => e,
+        None => {
+            let res =
+                "Puppy doesn't understand those times.\nPlease write them like 08:00, 14:00, 20:00";
+            return Err(Error::Validation(res.to_owned()));
+        }
+    };
+    times.push(start);
+    times.sort();
+    times.dedup();
+
+    if interval.num_seconds() % 86400 != 0 {
+        let res = "Puppy can only use extra times with an interval of whole days.";
+        return Err(Error::Validation(res.to_owned()));
+    }
+
+    Ok(times)
+}
+
+pub fn format_times(times: &[NaiveTime]) -> String {
+    times
+        .iter()
+        .map(|t| t.format("%H:%M").to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Interval description including any times of day, e.g. `1 day at 08:00, 20:00`
+pub fn schedule_to_string(interval: &PgInterval, times: &[NaiveTime]) -> String {
+    match times.is_empty() {
+        true => pginterval_to_string(interval),
+        false => format!(
+            "{} at {}",
+            pginterval_to_string(interval),
+            format_times(times)
+        ),
+    }
+}
+
+/// Whether the occurrence at `next_run` is the last one, either because it
+/// uses up the allowed number of runs or the one after it is past the end date
+pub fn is_final_run(
+    next_run: &DateTime<Utc>,
+    interval: &PgInterval,
+    times: &[NaiveTime],
+    timezone: &PgInterval,
+    runs: i32,
+    max_runs: Option<i32>,
+    end_date: Option<DateTime<Utc>>,
+) -> bool {
+    if let Some(max) = max_runs {
+        if runs + 1 >= max {
+            return true;
+        }
+    }
+
+    match end_date {
+        Some(end) => next_occurrence(next_run, interval, times, timezone) >= end,
+        None => false,
+    }
+}
+
+/// End of the given local date as a UTC datetime, so an end date includes the whole day.
+/// `None` for dates at the very end of what chrono can count to.
+pub fn end_of_local_day(date: NaiveDate, timezone: &PgInterval) -> Option<DateTime<Utc>> {
+    let offset = TimeDelta::microseconds(timezone.microseconds);
+
+    date.succ_opt()?
+        .and_time(NaiveTime::MIN)
+        .checked_sub_signed(offset)
+        .map(|e| e.and_utc())
+}
+
+/// Describes when a task stops, `None` if it runs forever
+pub fn end_to_string(
+    runs: i32,
+    max_runs: Option<i32>,
+    end_date: Option<DateTime<Utc>>,
+    timezone: &PgInterval,
+) -> Option<String> {
+    let mut res: Vec<String> = Vec::new();
+
+    if let Some(max) = max_runs {
+        res.push(format!("{} of {} reminders left", (max - runs).max(0), max));
+    }
+
+    if let Some(end) = end_date {
+        let last_day = end.naive_utc() + TimeDelta::microseconds(timezone.microseconds)
+            - TimeDelta::seconds(1);
+        res.push(format!("ends after {}", last_day.date().format("%F")));
+    }
+
+    match res.is_empty() {
+        true => None,
+        false => Some(res.join(", ")),
+    }
+}
+
+/// Timezone offset from the hour and minutes a user gives, None if it's
+/// outside -12:00 to +14:59. Minutes follow the sign of the hour.
+pub fn timezone_interval(hour: i8, minutes: i8) -> Option<PgInterval> {
+    if !(-12..=14).contains(&hour) || !(0..=59).contains(&minutes) {
+        return None;
+    }
+
+    let delta = match hour >= 0 {
+        true => TimeDelta::minutes(minutes as i64) + TimeDelta::hours(hour as i64),
+        false => TimeDelta::minutes(-(minutes as i64)) + TimeDelta::hours(hour as i64),
+    };
+
+    PgInterval::try_from(delta).ok()
+}
+
+/// Schedule interval from its parts, each part has to be 1 to 120 when set
+pub fn schedule_interval(
+    days: Option<i64>,
+    hours: Option<i64>,
+    minutes: Option<i64>,
+) -> Option<TimeDelta> {
+    let mut duration = TimeDelta::zero();
+
+    for (part, unit) in [
+        (days, TimeDelta::days(1)),
+        (hours, TimeDelta::hours(1)),
+        (minutes, TimeDelta::minutes(1)),
+    ] {
+        if let Some(e) = part {
+            if !(1..=120).contains(&e) {
+                return None;
+            }
+            duration += unit * e as i32;
+        }
+    }
+
+    Some(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::{Clock, ManualClock};
+
+    fn tz(hours: i64, minutes: i64) -> PgInterval {
+        PgInterval::try_from(TimeDelta::hours(hours) + TimeDelta::minutes(minutes)).unwrap()
+    }
+
+    fn at(datetime: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(datetime).unwrap().to_utc()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn first_run_later_today() {
+        let clock = ManualClock::new(at("2025-01-01T06:00:00Z"));
+
+        assert_eq!(
+            first_run(clock.now(), &tz(0, 0), time(8, 0), &[]),
+            at("2025-01-01T08:00:00Z")
+        );
+    }
+
+    #[test]
+    fn first_run_rolls_to_tomorrow() {
+        let clock = ManualClock::new(at("2025-01-01T08:00:00Z"));
+
+        assert_eq!(
+            first_run(clock.now(), &tz(0, 0), time(8, 0), &[]),
+            at("2025-01-02T08:00:00Z")
+        );
+    }
+
+    #[test]
+    fn first_run_ahead_of_utc() {
+        // 20:00 UTC is already 06:00 tomorrow in UTC+10
+        let clock = ManualClock::new(at("2025-01-01T20:00:00Z"));
+
+        assert_eq!(
+            first_run(clock.now(), &tz(10, 0), time(8, 0), &[]),
+            at("2025-01-01T22:00:00Z")
+        );
+
+        // Late in the day the next local 00:00 is more than a day after midnight UTC
+        assert_eq!(
+            first_run(clock.now(), &tz(14, 0), time(0, 0), &[]),
+            at("2025-01-02T10:00:00Z")
+        );
+    }
+
+    #[test]
+    fn first_run_behind_utc() {
+        // 01:00 UTC is still 20:00 yesterday in UTC-5
+        let clock = ManualClock::new(at("2025-01-02T01:00:00Z"));
+
+        assert_eq!(
+            first_run(clock.now(), &tz(-5, 0), time(23, 0), &[]),
+            at("2025-01-02T04:00:00Z")
+        );
+    }
+
+    #[test]
+    fn first_run_half_hour_offset() {
+        let clock = ManualClock::new(at("2025-01-01T00:00:00Z"));
+
+        assert_eq!(
+            first_run(clock.now(), &tz(5, 30), time(9, 0), &[]),
+            at("2025-01-01T03:30:00Z")
+        );
+    }
+
+    #[test]
+    fn first_run_picks_earliest_time() {
+        let clock = ManualClock::new(at("2025-01-01T10:00:00Z"));
+
+        assert_eq!(
+            first_run(
+                clock.now(),
+                &tz(0, 0),
+                time(8, 0),
+                &[time(8, 0), time(14, 0), time(20, 0)]
+            ),
+            at("2025-01-01T14:00:00Z")
+        );
+    }
+
+    #[test]
+    fn next_occurrence_adds_interval() {
+        let interval = PgInterval::try_from(TimeDelta::hours(6)).unwrap();
+
+        assert_eq!(
+            next_occurrence(&at("2025-01-01T22:00:00Z"), &interval, &[], &tz(10, 0)),
+            at("2025-01-02T04:00:00Z")
+        );
+    }
+
+    #[test]
+    fn next_occurrence_follows_local_times() {
+        let interval = PgInterval::try_from(TimeDelta::days(2)).unwrap();
+        let times = [time(8, 0), time(20, 0)];
+
+        // 08:00 in UTC-5 moves to 20:00 the same local day
+        let first = at("2025-01-01T13:00:00Z");
+        let second = next_occurrence(&first, &interval, &times, &tz(-5, 0));
+        assert_eq!(second, at("2025-01-02T01:00:00Z"));
+
+        // then skips ahead by the interval to 08:00
+        assert_eq!(
+            next_occurrence(&second, &interval, &times, &tz(-5, 0)),
+            at("2025-01-03T13:00:00Z")
+        );
+    }
+
+    #[test]
+    fn end_of_local_day_uses_offset() {
+        assert_eq!(
+            end_of_local_day(NaiveDate::from_ymd_opt(2025, 3, 1).unwrap(), &tz(10, 0)),
+            Some(at("2025-03-01T14:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn end_of_local_day_past_the_last_date() {
+        assert_eq!(end_of_local_day(NaiveDate::MAX, &tz(10, 0)), None);
+    }
+
+    #[test]
+    fn timezone_interval_checks_range() {
+        assert_eq!(timezone_interval(10, 30), Some(tz(10, 30)));
+        assert_eq!(timezone_interval(-3, 30), Some(tz(-3, -30)));
+        assert_eq!(timezone_interval(15, 0), None);
+        assert_eq!(timezone_interval(-13, 0), None);
+        assert_eq!(timezone_interval(1, 60), None);
+        assert_eq!(timezone_interval(1, -5), None);
+    }
+
+    #[test]
+    fn schedule_interval_checks_parts() {
+        assert_eq!(
+            schedule_interval(Some(1), Some(2), None),
+            Some(TimeDelta::hours(26))
+        );
+        assert_eq!(schedule_interval(None, None, None), Some(TimeDelta::zero()));
+        assert_eq!(schedule_interval(Some(i64::MAX), None, None), None);
+        assert_eq!(schedule_interval(None, Some(0), None), None);
+    }
+
+    #[test]
+    fn extra_times_include_the_start() {
+        assert_eq!(
+            times_with_extra(Some("20:00, 14:00"), time(8, 0), &TimeDelta::days(1)).unwrap(),
+            vec![time(8, 0), time(14, 0), time(20, 0)]
+        );
+        assert!(times_with_extra(None, time(8, 0), &TimeDelta::hours(6))
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            times_with_extra(Some("8am"), time(8, 0), &TimeDelta::days(1)),
+            Err(Error::Validation(_))
+        ));
+        assert!(matches!(
+            times_with_extra(Some("14:00"), time(8, 0), &TimeDelta::hours(36)),
+            Err(Error::Validation(_))
+        ));
+    }
+}